@@ -0,0 +1,111 @@
+use std::{
+    env,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use crate::{config::Config, terminal::set_clipboard};
+
+/// Whether kilo is running over SSH, where the helper commands
+/// would use the clipboard of the remote machine
+fn is_remote() -> bool {
+    env::var_os("SSH_CONNECTION").is_some() || env::var_os("SSH_TTY").is_some()
+}
+
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+/// Runs the copy command with the text as its input
+fn run_copy(command: &str, text: &str) -> Result<(), String> {
+    // the output isn't captured since tools like xclip stay in the background
+    // holding the clipboard, and would keep the pipes open
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // dropping stdin closes it, which ends the input
+        stdin
+            .write_all(text.as_bytes())
+            .map_err(|e| e.to_string())?;
+    }
+
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("'{}' failed with {}", command, status));
+    }
+    Ok(())
+}
+
+/// Runs the paste command, returning its output
+fn run_paste(command: &str) -> Result<String, String> {
+    let output = shell(command)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.lines().next() {
+            Some(line) => line.to_owned(),
+            None => format!("'{}' failed with {}", command, output.status),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n"))
+}
+
+/// Puts the text on the system clipboard, through the terminal (OSC 52)
+/// and, when running locally, the copy command.
+pub fn copy(config: &Config, text: &str) -> Result<(), String> {
+    if config.osc52 {
+        set_clipboard(text).map_err(|e| e.to_string())?;
+    }
+    match &config.copy_command {
+        Some(command) if !is_remote() => run_copy(command, text),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the contents of the system clipboard if there's a paste command
+/// and kilo is running locally
+pub fn paste(config: &Config) -> Result<Option<String>, String> {
+    match &config.paste_command {
+        Some(command) if !is_remote() => run_paste(command).map(Some),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_and_paste_through_commands() {
+        // the commands are only used when running locally
+        env::remove_var("SSH_CONNECTION");
+        env::remove_var("SSH_TTY");
+
+        let path = env::temp_dir().join(format!("kilo-clipboard-{}", std::process::id()));
+        let mut config = Config::new();
+        config.osc52 = false;
+        config.copy_command = Some(format!("cat > '{}'", path.display()));
+        config.paste_command = Some(format!("cat '{}'", path.display()));
+
+        copy(&config, "héllo\r\nworld\n").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), "héllo\r\nworld\n".as_bytes());
+        assert_eq!(paste(&config).unwrap().as_deref(), Some("héllo\nworld\n"));
+        std::fs::remove_file(&path).unwrap();
+
+        config.paste_command = Some("echo 'no clipboard' >&2; exit 1".to_owned());
+        assert_eq!(paste(&config), Err("no clipboard".to_owned()));
+        config.copy_command = Some("exit 2".to_owned());
+        assert!(copy(&config, "text").is_err());
+
+        config.paste_command = None;
+        assert_eq!(paste(&config), Ok(None));
+    }
+}
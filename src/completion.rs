@@ -0,0 +1,62 @@
+use std::{env, fs, path::PathBuf};
+
+/// Replaces a leading `~` with the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), env::var_os("HOME")) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            PathBuf::from(home).join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Returns the paths which can complete `input`, sorted.
+/// Directories end with a `/`, hidden files are only included if `input` names one.
+pub fn complete_path(input: &str) -> Vec<String> {
+    // the part up to the last `/` is kept as typed (eg. with a `~`)
+    let (dir, prefix) = match input.rfind('/') {
+        Some(i) => input.split_at(i + 1),
+        None => ("", input),
+    };
+    let dir_path = if dir.is_empty() {
+        PathBuf::from(".")
+    } else {
+        expand_home(dir)
+    };
+    let Ok(entries) = fs::read_dir(dir_path) else {
+        return vec![];
+    };
+
+    let mut candidates = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            // follows symlinks
+            let is_dir = entry.path().is_dir();
+            Some(format!("{}{}{}", dir, name, if is_dir { "/" } else { "" }))
+        })
+        .collect::<Vec<_>>();
+    candidates.sort();
+    candidates
+}
+
+/// Returns the longest prefix shared by all the strings
+pub fn common_prefix(strings: &[String]) -> String {
+    let Some((first, rest)) = strings.split_first() else {
+        return String::new();
+    };
+    let mut len = first.len();
+    for s in rest {
+        len = first
+            .char_indices()
+            .zip(s.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, c), _)| i + c.len_utf8())
+            .min(len);
+    }
+    first[..len].to_owned()
+}
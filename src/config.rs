@@ -0,0 +1,273 @@
+use std::{collections::HashMap, env, fs, io, path::PathBuf, str::FromStr};
+
+use crate::{
+    filetype::{self, FileType},
+    keymap::{parse_keys, Keymap},
+    line::{Highlight, MAX_TAB_STOP},
+};
+
+const KILO_TAB_STOP: usize = 4;
+/// No. of seconds for which a status message is shown
+const KILO_MESSAGE_TIMEOUT: u64 = 5;
+/// No. of milliseconds to wait for the rest of an escape sequence after <esc>
+const KILO_ESCAPE_TIMEOUT: u64 = 100;
+
+/// Settings which apply to a single buffer
+#[derive(Clone, Copy)]
+pub struct Options {
+    pub tab_stop: usize,
+    /// Insert spaces instead of a tab character when Tab is pressed
+    pub expand_tabs: bool,
+    pub line_numbers: bool,
+}
+
+/// Per-filetype overrides for `Options`, unset fields fall back to the global value
+#[derive(Default, Clone, Copy)]
+struct Overrides {
+    tab_stop: Option<usize>,
+    expand_tabs: Option<bool>,
+    line_numbers: Option<bool>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Theme {
+    Default,
+    Bright,
+    /// No colors
+    Mono,
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Theme::Default),
+            "bright" => Ok(Theme::Bright),
+            "mono" => Ok(Theme::Mono),
+            _ => Err(format!(
+                "unknown theme '{}' (expected default, bright or mono)",
+                s
+            )),
+        }
+    }
+}
+
+impl Theme {
+    /// Returns the SGR (Select Graphic Rendition) escape sequence for a highlight.
+    /// Every sequence resets all the attributes (arg 0) before setting its own.
+    pub fn sgr(&self, hl: Highlight) -> &'static str {
+        match (self, hl) {
+            (Theme::Mono, Highlight::Match) => "\x1b[0;4m",
+            (Theme::Mono, Highlight::CurrentMatch) => "\x1b[0;7m",
+            (_, Highlight::Match) => "\x1b[0;30;43m",
+            (_, Highlight::CurrentMatch) => "\x1b[0;30;46m",
+            (_, Highlight::Selection) => "\x1b[0;7m",
+            (_, Highlight::Normal) | (Theme::Mono, _) => "\x1b[0m",
+            (Theme::Default, Highlight::Comment) => "\x1b[0;36m",
+            (Theme::Default, Highlight::Keyword1) => "\x1b[0;33m",
+            (Theme::Default, Highlight::Keyword2) => "\x1b[0;32m",
+            (Theme::Default, Highlight::String) => "\x1b[0;35m",
+            (Theme::Default, Highlight::Number) => "\x1b[0;31m",
+            (Theme::Bright, Highlight::Comment) => "\x1b[0;96m",
+            (Theme::Bright, Highlight::Keyword1) => "\x1b[0;93m",
+            (Theme::Bright, Highlight::Keyword2) => "\x1b[0;92m",
+            (Theme::Bright, Highlight::String) => "\x1b[0;95m",
+            (Theme::Bright, Highlight::Number) => "\x1b[0;91m",
+        }
+    }
+
+    /// Returns the SGR escape sequence used for the line number gutter
+    pub fn gutter(&self) -> &'static str {
+        match self {
+            Theme::Mono => "\x1b[0m",
+            _ => "\x1b[0;90m",
+        }
+    }
+}
+
+pub struct Config {
+    pub options: Options,
+    pub theme: Theme,
+    /// No. of seconds for which a status message is shown
+    pub message_timeout: u64,
+    /// No. of milliseconds to wait for the rest of an escape sequence after <esc>.
+    /// Esc followed by another key within this time is read as Alt + the key.
+    pub escape_timeout: u64,
+    pub keymap: Keymap,
+    /// Whether copied text is sent to the terminal's clipboard (OSC 52)
+    pub osc52: bool,
+    /// Shell command which receives copied text on stdin, eg. `xclip -selection clipboard`
+    pub copy_command: Option<String>,
+    /// Shell command which prints the clipboard, eg. `xclip -selection clipboard -o`
+    pub paste_command: Option<String>,
+    /// Overrides keyed by filetype name
+    filetypes: HashMap<String, Overrides>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self {
+            options: Options {
+                tab_stop: KILO_TAB_STOP,
+                expand_tabs: false,
+                line_numbers: false,
+            },
+            theme: Theme::Default,
+            message_timeout: KILO_MESSAGE_TIMEOUT,
+            escape_timeout: KILO_ESCAPE_TIMEOUT,
+            keymap: Keymap::new(),
+            osc52: true,
+            copy_command: None,
+            paste_command: None,
+            filetypes: HashMap::new(),
+        }
+    }
+
+    /// Returns the location of the config file:
+    /// `$XDG_CONFIG_HOME/kilo/config` or `~/.config/kilo/config`
+    pub fn path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("kilo").join("config"))
+    }
+
+    /// Loads the config file if it exists.
+    ///
+    /// Invalid entries are skipped (leaving the default in place) and
+    /// reported in the returned list of errors.
+    pub fn load() -> (Self, Vec<String>) {
+        let mut config = Self::new();
+        let Some(path) = Self::path() else {
+            return (config, vec![]);
+        };
+
+        let errors = match fs::read_to_string(&path) {
+            Ok(contents) => config
+                .parse(&contents)
+                .into_iter()
+                .map(|(lineno, e)| format!("{}:{}: {}", path.display(), lineno, e))
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => vec![format!("{}: {}", path.display(), e)],
+        };
+
+        (config, errors)
+    }
+
+    /// Parses lines of the form `key = value`.
+    /// Options under a `[filetype]` header only apply to files of that type.
+    /// Lines under the `[keys]` header bind key sequences to commands (`Ctrl-K Ctrl-C = quit`),
+    /// the command `none` removes a binding.
+    ///
+    /// Returns a list of (line no., error).
+    fn parse(&mut self, contents: &str) -> Vec<(usize, String)> {
+        let mut errors = Vec::new();
+        let mut section: Option<String> = None;
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                if name != "keys" && filetype::find(name).is_none() {
+                    errors.push((i + 1, format!("unknown filetype '{}'", name)));
+                }
+                section = Some(name.to_owned());
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                errors.push((i + 1, format!("expected 'key = value', found '{}'", line)));
+                continue;
+            };
+
+            let result = match &section {
+                Some(name) if name == "keys" => self.bind(key.trim(), value.trim()),
+                Some(name) => {
+                    let overrides = self.filetypes.entry(name.clone()).or_default();
+                    Self::set_override(overrides, key.trim(), value.trim())
+                }
+                None => self.set(key.trim(), value.trim()),
+            };
+            if let Err(e) = result {
+                errors.push((i + 1, e));
+            }
+        }
+
+        errors
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "theme" => self.theme = value.parse()?,
+            "message_timeout" => self.message_timeout = parse_value(key, value)?,
+            "escape_timeout" => self.escape_timeout = parse_value(key, value)?,
+            "osc52" => self.osc52 = parse_value(key, value)?,
+            // an empty command disables it
+            "copy_command" => self.copy_command = (!value.is_empty()).then(|| value.to_owned()),
+            "paste_command" => self.paste_command = (!value.is_empty()).then(|| value.to_owned()),
+            _ => {
+                let mut overrides = Overrides::default();
+                Self::set_override(&mut overrides, key, value)?;
+                self.options = overrides.apply(self.options);
+            }
+        }
+        Ok(())
+    }
+
+    fn bind(&mut self, keys: &str, command: &str) -> Result<(), String> {
+        let keys = parse_keys(keys)?;
+        let command = match command {
+            "none" => None,
+            command => Some(command.parse()?),
+        };
+        self.keymap.bind(keys, command);
+        Ok(())
+    }
+
+    fn set_override(overrides: &mut Overrides, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "tab_stop" => {
+                let tab_stop = parse_value(key, value)?;
+                if !(1..=MAX_TAB_STOP).contains(&tab_stop) {
+                    return Err(format!("tab_stop must be between 1 and {}", MAX_TAB_STOP));
+                }
+                overrides.tab_stop = Some(tab_stop);
+            }
+            "expand_tabs" => overrides.expand_tabs = Some(parse_value(key, value)?),
+            "line_numbers" => overrides.line_numbers = Some(parse_value(key, value)?),
+            _ => return Err(format!("unknown option '{}'", key)),
+        }
+        Ok(())
+    }
+
+    /// Returns the options for a buffer of the given filetype
+    pub fn options_for(&self, filetype: Option<&FileType>) -> Options {
+        match filetype.and_then(|ft| self.filetypes.get(ft.name)) {
+            Some(overrides) => overrides.apply(self.options),
+            None => self.options,
+        }
+    }
+}
+
+impl Overrides {
+    fn apply(&self, options: Options) -> Options {
+        Options {
+            tab_stop: self.tab_stop.unwrap_or(options.tab_stop),
+            expand_tabs: self.expand_tabs.unwrap_or(options.expand_tabs),
+            line_numbers: self.line_numbers.unwrap_or(options.line_numbers),
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, key))
+}
@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use crate::syntax::{self, Syntax};
+
+/// Language profile of a file
+pub struct FileType {
+    pub name: &'static str,
+    /// File extensions (without the leading dot)
+    pub extensions: &'static [&'static str],
+    /// Exact file names
+    pub filenames: &'static [&'static str],
+    /// Interpreters which identify the file type in a shebang (`#!`) line
+    pub interpreters: &'static [&'static str],
+    pub syntax: Option<&'static Syntax>,
+}
+
+/// Filetype database
+const FILETYPES: &[FileType] = &[
+    FileType {
+        name: "rust",
+        extensions: &["rs"],
+        filenames: &[],
+        interpreters: &[],
+        syntax: Some(&syntax::RUST),
+    },
+    FileType {
+        name: "c",
+        extensions: &["c", "h"],
+        filenames: &[],
+        interpreters: &[],
+        syntax: Some(&syntax::C),
+    },
+    FileType {
+        name: "cpp",
+        extensions: &["cpp", "hpp", "cc", "cxx", "hh"],
+        filenames: &[],
+        interpreters: &[],
+        syntax: Some(&syntax::C),
+    },
+    FileType {
+        name: "python",
+        extensions: &["py", "pyi"],
+        filenames: &["SConstruct", "SConscript"],
+        interpreters: &["python"],
+        syntax: Some(&syntax::PYTHON),
+    },
+    FileType {
+        name: "markdown",
+        extensions: &["md", "markdown"],
+        filenames: &[],
+        interpreters: &[],
+        syntax: Some(&syntax::MARKDOWN),
+    },
+    FileType {
+        name: "toml",
+        extensions: &["toml"],
+        filenames: &["Cargo.lock", "Pipfile", "poetry.lock"],
+        interpreters: &[],
+        syntax: Some(&syntax::TOML),
+    },
+    FileType {
+        name: "shell",
+        extensions: &["sh", "bash", "zsh"],
+        filenames: &[".bashrc", ".bash_profile", ".profile", ".zshrc", "PKGBUILD"],
+        interpreters: &["sh", "bash", "zsh", "dash", "ksh"],
+        syntax: Some(&syntax::SHELL),
+    },
+    FileType {
+        name: "make",
+        extensions: &["mk", "mak"],
+        filenames: &["Makefile", "makefile", "GNUmakefile"],
+        interpreters: &["make"],
+        syntax: Some(&syntax::MAKE),
+    },
+    FileType {
+        name: "text",
+        extensions: &["txt"],
+        filenames: &[],
+        interpreters: &[],
+        syntax: None,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static FileType> {
+    FILETYPES.iter().find(|ft| ft.name == name)
+}
+
+/// Detects the type of a file from its name or,
+/// if that doesn't match anything, from the shebang in its first line.
+pub fn detect(filename: &str, first_line: Option<&str>) -> Option<&'static FileType> {
+    let path = Path::new(filename);
+    let name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or(filename);
+    let ext = path.extension().and_then(|s| s.to_str());
+
+    FILETYPES
+        .iter()
+        .find(|ft| ft.filenames.contains(&name))
+        .or_else(|| ext.and_then(|ext| FILETYPES.iter().find(|ft| ft.extensions.contains(&ext))))
+        .or_else(|| {
+            let interpreter = first_line.and_then(shebang_interpreter)?;
+            FILETYPES
+                .iter()
+                .find(|ft| ft.interpreters.contains(&interpreter))
+        })
+}
+
+/// Extracts the name of the interpreter from a shebang line,
+/// eg. `#!/usr/bin/env python3.11` -> `python`
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut args = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = args.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // skip flags passed to env (eg. `env -S`)
+        program = args.find(|arg| !arg.starts_with('-'))?;
+    }
+    Some(program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.'))
+}
@@ -0,0 +1,82 @@
+use std::{collections::HashMap, env, fs, io, path::PathBuf};
+
+/// Max. no. of entries kept for each kind of prompt
+const HISTORY_SIZE: usize = 100;
+
+/// Earlier inputs of the prompts, keyed by the kind of prompt (eg. `search`)
+pub struct History {
+    /// Oldest entry first
+    entries: HashMap<String, Vec<String>>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Returns the location of the history file:
+    /// `$XDG_DATA_HOME/kilo/history` or `~/.local/share/kilo/history`
+    fn path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?)
+                .join(".local")
+                .join("share"),
+        };
+        Some(dir.join("kilo").join("history"))
+    }
+
+    /// Loads the history file if it exists.
+    /// Each line holds the kind of prompt and an entry separated by a tab.
+    pub fn load() -> Self {
+        let mut history = Self::new();
+        let Some(contents) = Self::path().and_then(|path| fs::read_to_string(path).ok()) else {
+            return history;
+        };
+
+        for line in contents.lines() {
+            if let Some((kind, entry)) = line.split_once('\t') {
+                history.push(kind, entry);
+            }
+        }
+        history
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = Self::path() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut contents = String::new();
+        for (kind, entries) in &self.entries {
+            for entry in entries {
+                contents.push_str(&format!("{}\t{}\n", kind, entry));
+            }
+        }
+        fs::write(path, contents)
+    }
+
+    /// Adds an entry, moving it to the end if it's already present
+    pub fn push(&mut self, kind: &str, entry: &str) {
+        // entries are stored one per line
+        if entry.is_empty() || entry.contains(char::is_control) {
+            return;
+        }
+
+        let entries = self.entries.entry(kind.to_owned()).or_default();
+        entries.retain(|e| e != entry);
+        if entries.len() == HISTORY_SIZE {
+            entries.remove(0);
+        }
+        entries.push(entry.to_owned());
+    }
+
+    pub fn get(&self, kind: &str) -> &[String] {
+        self.entries.get(kind).map_or(&[], Vec::as_slice)
+    }
+}
@@ -0,0 +1,162 @@
+use std::ops::Range;
+
+use crate::{
+    is_backspace,
+    key::{ctrl_key, EditorKey, Modifiers, SpecialKey},
+    killring::KillRing,
+    line::{is_separator, Line},
+};
+
+/// The prompt input never contains tabs
+const INPUT_TAB_STOP: usize = 1;
+
+const CTRL_A: char = ctrl_key('a');
+const CTRL_E: char = ctrl_key('e');
+const CTRL_K: char = ctrl_key('k');
+const CTRL_U: char = ctrl_key('u');
+const CTRL_W: char = ctrl_key('w');
+const CTRL_Y: char = ctrl_key('y');
+
+/// A single line of editable text, used by the prompt
+pub struct Input {
+    line: Line,
+    /// Cursor X coordinate (for chars)
+    cx: usize,
+    /// First visible screen column
+    col_offset: usize,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self {
+            line: Line::new(vec![], INPUT_TAB_STOP),
+            cx: 0,
+            col_offset: 0,
+        }
+    }
+
+    /// Replaces the text, moving the cursor to the end
+    pub fn set_text(&mut self, text: &str) {
+        self.line.chars.clear();
+        self.cx = 0;
+        self.insert(text);
+    }
+
+    pub fn text(&self) -> String {
+        self.line.chars.iter().collect()
+    }
+
+    /// Handles a line editing key.
+    /// Returns false if the key isn't one, leaving it for the caller.
+    pub fn process_key(&mut self, key: EditorKey, kill_ring: &mut KillRing) -> bool {
+        match key {
+            EditorKey::ArrowLeft => self.cx = self.line.prev_cx(self.cx),
+            EditorKey::ArrowRight => self.cx = self.line.next_cx(self.cx),
+            EditorKey::Modified(SpecialKey::Left, Modifiers::CTRL) => self.cx = self.word_start(),
+            EditorKey::Modified(SpecialKey::Right, Modifiers::CTRL) => self.cx = self.word_end(),
+            EditorKey::Home | EditorKey::Char(CTRL_A) => self.cx = 0,
+            EditorKey::End | EditorKey::Char(CTRL_E) => self.cx = self.line.size(),
+            key if is_backspace(key) => {
+                let start = self.line.prev_cx(self.cx);
+                self.remove(start..self.cx);
+            }
+            EditorKey::Delete => {
+                let end = self.line.next_cx(self.cx);
+                self.remove(self.cx..end);
+            }
+            EditorKey::Char(CTRL_W)
+            | EditorKey::Modified(SpecialKey::Backspace, Modifiers::ALT) => {
+                let start = self.word_start();
+                let killed = self.remove(start..self.cx);
+                kill_ring.push(killed);
+            }
+            EditorKey::Alt('d') => {
+                let end = self.word_end();
+                let killed = self.remove(self.cx..end);
+                kill_ring.push(killed);
+            }
+            EditorKey::Char(CTRL_K) => {
+                let killed = self.remove(self.cx..self.line.size());
+                kill_ring.push(killed);
+            }
+            EditorKey::Char(CTRL_U) => {
+                let killed = self.remove(0..self.cx);
+                kill_ring.push(killed);
+            }
+            EditorKey::Char(CTRL_Y) => {
+                if let Some(text) = kill_ring.yank() {
+                    self.insert(text);
+                }
+            }
+            EditorKey::Char(c) if !c.is_control() => self.insert(&c.to_string()),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Inserts text at the cursor, control chars (eg. newlines) are dropped
+    pub fn insert(&mut self, text: &str) {
+        let chars = text.chars().filter(|c| !c.is_control()).collect::<Vec<_>>();
+        let n = chars.len();
+        self.line.chars.splice(self.cx..self.cx, chars);
+        self.line.update(INPUT_TAB_STOP);
+        self.cx += n;
+    }
+
+    /// Removes the chars in `range`, leaving the cursor at its start
+    fn remove(&mut self, range: Range<usize>) -> String {
+        self.cx = range.start;
+        let removed = self.line.chars.drain(range).collect();
+        self.line.update(INPUT_TAB_STOP);
+        removed
+    }
+
+    /// Returns the start of the word before the cursor
+    fn word_start(&self) -> usize {
+        let chars = &self.line.chars[..self.cx];
+        let end = chars
+            .iter()
+            .rposition(|c| !is_separator(*c))
+            .map_or(0, |i| i + 1);
+        chars[..end]
+            .iter()
+            .rposition(|c| is_separator(*c))
+            .map_or(0, |i| i + 1)
+    }
+
+    /// Returns the end of the word after the cursor
+    fn word_end(&self) -> usize {
+        let chars = &self.line.chars[self.cx..];
+        let start = chars
+            .iter()
+            .position(|c| !is_separator(*c))
+            .unwrap_or(chars.len());
+        let end = chars[start..]
+            .iter()
+            .position(|c| is_separator(*c))
+            .map_or(chars.len(), |i| start + i);
+        self.cx + end
+    }
+
+    /// Returns the part of the text which fits in `width` columns
+    /// and the screen column of the cursor within it.
+    /// The text is scrolled horizontally to keep the cursor visible.
+    pub fn render(&mut self, width: usize) -> (String, usize) {
+        let rx = self.line.cx_to_rx(self.cx);
+        // leave space for the cursor when it's past the end of the text
+        let cursor_width = self.line.width_at(self.cx).max(1);
+        if rx < self.col_offset {
+            self.col_offset = rx;
+        }
+        if rx + cursor_width > self.col_offset + width {
+            self.col_offset = rx + cursor_width - width.min(rx + cursor_width);
+        }
+
+        let text = self
+            .line
+            .visible(self.col_offset, width)
+            .flat_map(|(_, chars)| chars.iter())
+            .collect();
+        (text, rx - self.col_offset)
+    }
+}
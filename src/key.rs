@@ -0,0 +1,181 @@
+use std::ops::BitOr;
+
+pub const fn ctrl_key(k: char) -> char {
+    // when you press Ctrl in combination w/ other key in the terminal
+    // a modified character is sent w/ bits 5 and 6 stripped (set to '0')
+    // in the character corresponding to the key pressed
+    let v = (k as u8) & 0x1f;
+    v as char
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum EditorKey {
+    Char(char),
+    /// A character (or control char, eg. `\r` for Enter) typed while holding Alt (or Meta)
+    Alt(char),
+    /// Ctrl + a char whose control code is also sent by another key, eg. Ctrl-I and Tab.
+    /// Only reported by terminals which tell them apart (kitty keyboard protocol).
+    Ctrl(char),
+    ArrowLeft,
+    ArrowRight,
+    ArrowUp,
+    ArrowDown,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Delete,
+    Backspace,
+    ShiftTab,
+    Insert,
+    /// Function keys F1-F12
+    F(u8),
+    /// A special key pressed along with modifiers (at least one), eg. Ctrl + Right
+    Modified(SpecialKey, Modifiers),
+    /// Left mouse button pressed at the screen position (x, y), starting from 0
+    MouseDown(usize, usize),
+    /// Mouse moved to (x, y) while holding the left button
+    MouseDrag(usize, usize),
+    ScrollUp,
+    ScrollDown,
+}
+
+/// Keys other than chars which can be pressed along with modifiers
+#[derive(PartialEq, Clone, Copy)]
+pub enum SpecialKey {
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    Backspace,
+}
+
+/// A set of modifier keys, the bits being the same as in xterm's escape sequences
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE: Self = Self(0);
+    pub const SHIFT: Self = Self(1);
+    pub const ALT: Self = Self(2);
+    pub const CTRL: Self = Self(4);
+
+    /// Parses the modifier parameter of an escape sequence, which is 1 + the bits.
+    /// Meta (8) is treated as Alt.
+    pub fn from_param(param: &str) -> Self {
+        let bits = param.parse::<u8>().map_or(0, |n| n.saturating_sub(1));
+        Self(bits & 0b111 | (bits & 0b1000) >> 2)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl EditorKey {
+    /// Returns the key for Ctrl + `c`
+    pub fn ctrl(c: char) -> Self {
+        let c = c.to_ascii_lowercase();
+        match ctrl_key(c) {
+            '\t' | '\r' | '\x1b' => EditorKey::Ctrl(c),
+            code => EditorKey::Char(code),
+        }
+    }
+
+    /// Splits the key into a special key and the modifiers held along with it
+    pub fn special(self) -> Option<(SpecialKey, Modifiers)> {
+        let key = match self {
+            EditorKey::ArrowLeft => SpecialKey::Left,
+            EditorKey::ArrowRight => SpecialKey::Right,
+            EditorKey::ArrowUp => SpecialKey::Up,
+            EditorKey::ArrowDown => SpecialKey::Down,
+            EditorKey::Home => SpecialKey::Home,
+            EditorKey::End => SpecialKey::End,
+            EditorKey::PageUp => SpecialKey::PageUp,
+            EditorKey::PageDown => SpecialKey::PageDown,
+            EditorKey::Insert => SpecialKey::Insert,
+            EditorKey::Delete => SpecialKey::Delete,
+            EditorKey::Backspace => SpecialKey::Backspace,
+            EditorKey::Modified(key, modifiers) => return Some((key, modifiers)),
+            _ => return None,
+        };
+        Some((key, Modifiers::NONE))
+    }
+
+    /// Returns the special key pressed along with the modifiers,
+    /// keys without modifiers have their own variants
+    pub fn from_special(key: SpecialKey, modifiers: Modifiers) -> Self {
+        if modifiers != Modifiers::NONE {
+            return EditorKey::Modified(key, modifiers);
+        }
+        match key {
+            SpecialKey::Left => EditorKey::ArrowLeft,
+            SpecialKey::Right => EditorKey::ArrowRight,
+            SpecialKey::Up => EditorKey::ArrowUp,
+            SpecialKey::Down => EditorKey::ArrowDown,
+            SpecialKey::Home => EditorKey::Home,
+            SpecialKey::End => EditorKey::End,
+            SpecialKey::PageUp => EditorKey::PageUp,
+            SpecialKey::PageDown => EditorKey::PageDown,
+            SpecialKey::Insert => EditorKey::Insert,
+            SpecialKey::Delete => EditorKey::Delete,
+            SpecialKey::Backspace => EditorKey::Backspace,
+        }
+    }
+
+    /// Adds modifiers to a special key, other keys are returned as is
+    pub fn with_modifiers(self, modifiers: Modifiers) -> Self {
+        match self.special() {
+            Some((key, held)) => Self::from_special(key, held | modifiers),
+            None => self,
+        }
+    }
+
+    /// Returns the key without Shift, if it's a movement which extends the selection
+    pub fn unshifted(self) -> Option<Self> {
+        match self {
+            EditorKey::Modified(
+                key @ (SpecialKey::Left
+                | SpecialKey::Right
+                | SpecialKey::Up
+                | SpecialKey::Down
+                | SpecialKey::Home
+                | SpecialKey::End
+                | SpecialKey::PageUp
+                | SpecialKey::PageDown),
+                modifiers,
+            ) if modifiers.contains(Modifiers::SHIFT) => {
+                Some(Self::from_special(key, modifiers.without(Modifiers::SHIFT)))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_mouse(self) -> bool {
+        matches!(
+            self,
+            EditorKey::MouseDown(..)
+                | EditorKey::MouseDrag(..)
+                | EditorKey::ScrollUp
+                | EditorKey::ScrollDown
+        )
+    }
+}
@@ -0,0 +1,262 @@
+use std::str::FromStr;
+
+use crate::key::{ctrl_key, EditorKey, Modifiers};
+
+/// Named actions that can be bound to keys
+#[derive(Clone, Copy, PartialEq)]
+pub enum Command {
+    Quit,
+    Save,
+    Open,
+    Find,
+    Replace,
+    Goto,
+    Mark,
+    Copy,
+    Cut,
+    Paste,
+    /// Replaces the text just pasted with an older entry of the kill ring
+    PasteOlder,
+    NextTab,
+    PrevTab,
+    Undo,
+    Redo,
+}
+
+const COMMANDS: &[(&str, Command)] = &[
+    ("quit", Command::Quit),
+    ("save", Command::Save),
+    ("open", Command::Open),
+    ("find", Command::Find),
+    ("replace", Command::Replace),
+    ("goto", Command::Goto),
+    ("mark", Command::Mark),
+    ("copy", Command::Copy),
+    ("cut", Command::Cut),
+    ("paste", Command::Paste),
+    ("paste-older", Command::PasteOlder),
+    ("next-tab", Command::NextTab),
+    ("prev-tab", Command::PrevTab),
+    ("undo", Command::Undo),
+    ("redo", Command::Redo),
+];
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        COMMANDS
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, cmd)| *cmd)
+            .ok_or_else(|| format!("unknown command '{}'", s))
+    }
+}
+
+/// Names of keys which don't produce a printable character
+const KEY_NAMES: &[(&str, EditorKey)] = &[
+    ("Enter", EditorKey::Char('\r')),
+    ("Tab", EditorKey::Char('\t')),
+    ("Esc", EditorKey::Char('\x1b')),
+    ("Space", EditorKey::Char(' ')),
+    ("Backspace", EditorKey::Backspace),
+    ("Delete", EditorKey::Delete),
+    ("Left", EditorKey::ArrowLeft),
+    ("Right", EditorKey::ArrowRight),
+    ("Up", EditorKey::ArrowUp),
+    ("Down", EditorKey::ArrowDown),
+    ("PageUp", EditorKey::PageUp),
+    ("PageDown", EditorKey::PageDown),
+    ("Home", EditorKey::Home),
+    ("End", EditorKey::End),
+    ("Insert", EditorKey::Insert),
+    ("Shift-Tab", EditorKey::ShiftTab),
+    ("Ctrl-Space", EditorKey::Char('\0')),
+];
+
+/// Prefixes of special keys pressed along with modifiers, in the order they're shown
+const MODIFIER_NAMES: &[(&str, Modifiers)] = &[
+    ("Ctrl-", Modifiers::CTRL),
+    ("Alt-", Modifiers::ALT),
+    ("Shift-", Modifiers::SHIFT),
+];
+
+fn find_key_name(s: &str) -> Option<EditorKey> {
+    KEY_NAMES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        .map(|(_, key)| *key)
+}
+
+/// Parses a single key such as `Ctrl-S`, `Alt-x`, `PageUp`, `Ctrl-Shift-Left`, `F5` or `x`
+fn parse_key(s: &str) -> Result<EditorKey, String> {
+    if let Some(key) = find_key_name(s) {
+        return Ok(key);
+    }
+
+    let mut rest = s;
+    let mut modifiers = Modifiers::NONE;
+    while let Some((name, m)) = MODIFIER_NAMES.iter().find(|(name, _)| {
+        rest.get(..name.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(name))
+    }) {
+        modifiers = modifiers | *m;
+        rest = &rest[name.len()..];
+    }
+    if let Some((key, _)) = find_key_name(rest).and_then(EditorKey::special) {
+        if modifiers != Modifiers::NONE {
+            return Ok(EditorKey::from_special(key, modifiers));
+        }
+    }
+
+    let function_key = s.strip_prefix(['F', 'f']).and_then(|n| n.parse().ok());
+    if let Some(n @ 1..=12) = function_key {
+        return Ok(EditorKey::F(n));
+    }
+
+    if let Some(rest) = s
+        .strip_prefix("Alt-")
+        .or(s.strip_prefix("alt-"))
+        .or(s.strip_prefix("M-"))
+    {
+        let mut chars = rest.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(EditorKey::Alt(c));
+        }
+        // Alt + a named or Ctrl key, eg. `Alt-Enter`
+        if let Ok(EditorKey::Char(c)) = parse_key(rest) {
+            return Ok(EditorKey::Alt(c));
+        }
+    }
+
+    let lower = s.to_ascii_lowercase();
+    if let Some(rest) = lower.strip_prefix("ctrl-").or(lower.strip_prefix("c-")) {
+        let mut chars = rest.chars();
+        if let (Some(c @ ('a'..='z' | '[' | '\\' | ']' | '^' | '_')), None) =
+            (chars.next(), chars.next())
+        {
+            return Ok(EditorKey::ctrl(c));
+        }
+    }
+
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !c.is_control() => Ok(EditorKey::Char(c)),
+        _ => Err(format!("invalid key '{}'", s)),
+    }
+}
+
+/// Parses a space separated sequence of keys, eg. `Ctrl-K Ctrl-C`
+pub fn parse_keys(s: &str) -> Result<Vec<EditorKey>, String> {
+    let keys = s
+        .split_whitespace()
+        .map(parse_key)
+        .collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err("empty key sequence".to_owned());
+    }
+    Ok(keys)
+}
+
+pub fn key_name(key: EditorKey) -> String {
+    if let Some((name, _)) = KEY_NAMES.iter().find(|(_, k)| *k == key) {
+        return name.to_string();
+    }
+    match key {
+        EditorKey::Char(c) if (c as u32) < 0x20 => {
+            format!("Ctrl-{}", ((c as u8) | 0x40).to_ascii_uppercase() as char)
+        }
+        EditorKey::Char(c) => c.to_string(),
+        EditorKey::Alt(c) => format!("Alt-{}", key_name(EditorKey::Char(c))),
+        EditorKey::Ctrl(c) => format!("Ctrl-{}", c.to_ascii_uppercase()),
+        EditorKey::F(n) => format!("F{}", n),
+        EditorKey::Modified(key, modifiers) => {
+            let prefix = MODIFIER_NAMES
+                .iter()
+                .filter(|(_, m)| modifiers.contains(*m))
+                .map(|(name, _)| *name)
+                .collect::<String>();
+            let key = EditorKey::from_special(key, Modifiers::NONE);
+            format!("{}{}", prefix, key_name(key))
+        }
+        _ => "?".to_owned(),
+    }
+}
+
+pub fn keys_name(keys: &[EditorKey]) -> String {
+    keys.iter()
+        .map(|k| key_name(*k))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub enum Lookup {
+    Command(Command),
+    /// The keys are the start of a longer sequence
+    Pending,
+    Unbound,
+}
+
+pub struct Keymap {
+    bindings: Vec<(Vec<EditorKey>, Command)>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        let mut keymap = Self { bindings: vec![] };
+        for (k, cmd) in [
+            ('q', Command::Quit),
+            ('s', Command::Save),
+            ('o', Command::Open),
+            ('f', Command::Find),
+            ('r', Command::Replace),
+            ('g', Command::Goto),
+            ('t', Command::NextTab),
+            ('z', Command::Undo),
+            ('y', Command::Redo),
+            (' ', Command::Mark),
+            ('c', Command::Copy),
+            ('x', Command::Cut),
+            ('v', Command::Paste),
+        ] {
+            keymap.bind(vec![EditorKey::Char(ctrl_key(k))], Some(cmd));
+        }
+        keymap.bind(vec![EditorKey::Alt('v')], Some(Command::PasteOlder));
+        keymap
+    }
+
+    /// Binds a key sequence to a command, or removes the binding if `cmd` is `None`.
+    ///
+    /// Existing bindings which would make the sequence ambiguous
+    /// (one being a prefix of the other) are removed.
+    pub fn bind(&mut self, keys: Vec<EditorKey>, cmd: Option<Command>) {
+        self.bindings
+            .retain(|(k, _)| !k.starts_with(&keys) && !keys.starts_with(k));
+        if let Some(cmd) = cmd {
+            self.bindings.push((keys, cmd));
+        }
+    }
+
+    pub fn lookup(&self, keys: &[EditorKey]) -> Lookup {
+        let mut pending = false;
+        for (k, cmd) in &self.bindings {
+            if k == keys {
+                return Lookup::Command(*cmd);
+            }
+            pending |= k.starts_with(keys);
+        }
+        if pending {
+            Lookup::Pending
+        } else {
+            Lookup::Unbound
+        }
+    }
+
+    /// Returns the name of the first key sequence bound to the command
+    pub fn keys_for(&self, cmd: Command) -> Option<String> {
+        self.bindings
+            .iter()
+            .find(|(_, c)| *c == cmd)
+            .map(|(keys, _)| keys_name(keys))
+    }
+}
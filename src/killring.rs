@@ -0,0 +1,46 @@
+/// Max. no. of entries kept in the kill ring
+const KILL_RING_SIZE: usize = 32;
+
+/// Text removed by the kill commands, which can be pasted (yanked) back later
+pub struct KillRing {
+    /// Oldest entry first
+    entries: Vec<String>,
+    /// Index of the entry yanked last
+    current: usize,
+}
+
+impl KillRing {
+    pub fn new() -> Self {
+        Self {
+            entries: vec![],
+            current: 0,
+        }
+    }
+
+    pub fn push(&mut self, text: String) {
+        if text.is_empty() {
+            return;
+        }
+        if self.entries.len() == KILL_RING_SIZE {
+            self.entries.remove(0);
+        }
+        self.entries.push(text);
+        self.current = self.entries.len() - 1;
+    }
+
+    /// Returns the most recently killed text
+    pub fn yank(&mut self) -> Option<&str> {
+        self.current = self.entries.len().checked_sub(1)?;
+        self.entries.last().map(String::as_str)
+    }
+
+    /// Returns the entry before the one yanked last, wrapping around to the newest one
+    pub fn yank_older(&mut self) -> Option<&str> {
+        let n = self.entries.len();
+        if n == 0 {
+            return None;
+        }
+        self.current = (self.current + n - 1) % n;
+        Some(&self.entries[self.current])
+    }
+}
@@ -1,66 +1,373 @@
-const KILO_TAB_STOP: usize = 4;
+use std::cmp::{max, min};
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::syntax::{Syntax, HL_CHARS, HL_HEADINGS, HL_NUMBERS, HL_SECTIONS};
+
+pub const MAX_TAB_STOP: usize = 16;
+
+/// Used for replacing the visible part of graphemes cut off by the edge of the screen
+const BLANK: [char; MAX_TAB_STOP] = [' '; MAX_TAB_STOP];
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum Highlight {
+    #[default]
+    Normal,
+    Comment,
+    Keyword1,
+    Keyword2,
+    String,
+    Number,
+    /// A search match, drawn over the syntax highlighting
+    Match,
+    /// The search match under the cursor
+    CurrentMatch,
+    /// Selected text
+    Selection,
+}
+
+/// Tokenizer state carried over from the end of a line to the next one
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum HlState {
+    #[default]
+    Normal,
+    /// Inside a block comment
+    Comment,
+    /// Inside a multi-line string which is closed by the delimiter
+    String(&'static str),
+}
+
+/// A grapheme cluster (user-perceived character) of a line
+#[derive(Clone, Copy)]
+struct Grapheme {
+    /// Index of the first char in `chars`
+    cx: usize,
+    /// Index of the first char in `render`
+    ri: usize,
+    /// Screen column where the grapheme starts
+    rx: usize,
+    /// No. of screen columns taken by the grapheme
+    width: usize,
+}
 
 #[derive(Clone)]
 pub struct Line {
     pub chars: Vec<char>,
     pub render: Vec<char>,
+    graphemes: Vec<Grapheme>,
+    /// Highlight for every char in `chars`
+    pub hl: Vec<Highlight>,
+    /// State the line was highlighted with, `None` if it needs to be highlighted again
+    pub hl_start: Option<HlState>,
+    /// State at the end of the line
+    pub hl_end: HlState,
+}
+
+pub fn is_separator(c: char) -> bool {
+    c.is_whitespace() || ",.()+-/*=~%<>[]{};:&|!?^".contains(c)
 }
 
 impl Line {
+    pub fn new(chars: Vec<char>, tab_stop: usize) -> Self {
+        let mut line = Self {
+            chars,
+            render: vec![],
+            graphemes: vec![],
+            hl: vec![],
+            hl_start: None,
+            hl_end: HlState::Normal,
+        };
+        line.update(tab_stop);
+        line
+    }
+
+    /// Returns the no. of screen columns taken by the line
     pub fn rsize(&self) -> usize {
-        self.render.len()
+        self.graphemes.last().map_or(0, |g| g.rx + g.width)
     }
 
     pub fn size(&self) -> usize {
         self.chars.len()
     }
 
-    pub fn update(&mut self) {
-        let mut idx = 0;
+    pub fn update(&mut self, tab_stop: usize) {
         // NOTE: This doesn't change the allocated capacity
         // so if the line was large earlier and became smaller, it'd still use the same capacity
         self.render.clear();
+        self.graphemes.clear();
+        self.hl_start = None;
 
-        for ch in &self.chars {
-            if *ch == '\t' {
-                self.render.push(' ');
-                idx += 1;
-                while idx % KILO_TAB_STOP != 0 {
-                    self.render.push(' ');
-                    idx += 1;
-                }
+        let s = self.chars.iter().collect::<String>();
+        let (mut cx, mut rx) = (0, 0);
+
+        for g in s.graphemes(true) {
+            let ri = self.render.len();
+            let width = if g == "\t" {
+                let width = tab_stop - (rx % tab_stop);
+                self.render.extend(std::iter::repeat_n(' ', width));
+                width
             } else {
-                self.render.push(ch.to_owned());
-                idx += 1
-            }
+                self.render.extend(g.chars());
+                g.width()
+            };
+
+            self.graphemes.push(Grapheme { cx, ri, rx, width });
+            cx += g.chars().count();
+            rx += width;
+        }
+    }
+
+    /// Returns the index of the grapheme which contains the char at `cx`
+    fn grapheme_at(&self, cx: usize) -> Option<usize> {
+        match self.graphemes.binary_search_by_key(&cx, |g| g.cx) {
+            Ok(i) => Some(i),
+            Err(0) => None,
+            Err(i) if cx < self.size() => Some(i - 1),
+            Err(_) => None,
         }
     }
 
     pub fn cx_to_rx(&self, cx: usize) -> usize {
-        let mut rx = 0;
-        for i in 0..cx {
-            if self.chars[i] == '\t' {
-                rx += (KILO_TAB_STOP - 1) - (rx % KILO_TAB_STOP);
-            }
-            rx += 1
+        match self.grapheme_at(cx) {
+            Some(i) => self.graphemes[i].rx,
+            None => self.rsize(),
         }
-        rx
     }
 
     pub fn rx_to_cx(&self, rx: usize) -> usize {
-        let mut cur_rx = 0;
-        let mut cx = 0;
-        while cx < self.size() {
-            if self.chars[cx] == '\t' {
-                cur_rx += (KILO_TAB_STOP - 1) - (cur_rx % KILO_TAB_STOP);
+        self.graphemes
+            .iter()
+            .find(|g| g.rx + g.width > rx)
+            .map_or(self.size(), |g| g.cx)
+    }
+
+    /// Returns the no. of screen columns taken by the grapheme at `cx`
+    pub fn width_at(&self, cx: usize) -> usize {
+        self.grapheme_at(cx).map_or(0, |i| self.graphemes[i].width)
+    }
+
+    /// Moves `cx` back to the start of the grapheme it's in
+    pub fn snap_cx(&self, cx: usize) -> usize {
+        match self.grapheme_at(cx) {
+            Some(i) => self.graphemes[i].cx,
+            None => self.size(),
+        }
+    }
+
+    /// Returns the position of the grapheme after the one at `cx`
+    pub fn next_cx(&self, cx: usize) -> usize {
+        match self.grapheme_at(cx) {
+            Some(i) if i + 1 < self.graphemes.len() => self.graphemes[i + 1].cx,
+            _ => self.size(),
+        }
+    }
+
+    /// Returns the position of the grapheme before the one at `cx`
+    pub fn prev_cx(&self, cx: usize) -> usize {
+        let i = self.grapheme_at(cx).unwrap_or(self.graphemes.len());
+        match i {
+            0 => 0,
+            i => self.graphemes[i - 1].cx,
+        }
+    }
+
+    /// Returns the graphemes that are visible between the screen columns
+    /// `start` and `start + width` as (cx, rendered chars).
+    /// Wide characters which are cut off by either edge are replaced by spaces.
+    pub fn visible(&self, start: usize, width: usize) -> impl Iterator<Item = (usize, &[char])> {
+        let end = start + width;
+        self.graphemes
+            .iter()
+            .enumerate()
+            .skip_while(move |(_, g)| g.rx + g.width <= start)
+            .take_while(move |(_, g)| g.rx < end)
+            .map(move |(i, g)| {
+                if g.rx < start || g.rx + g.width > end {
+                    let visible = min(g.rx + g.width, end) - max(g.rx, start);
+                    (g.cx, &BLANK[..min(visible, BLANK.len())])
+                } else {
+                    let ri_end = self
+                        .graphemes
+                        .get(i + 1)
+                        .map_or(self.render.len(), |g| g.ri);
+                    (g.cx, &self.render[g.ri..ri_end])
+                }
+            })
+    }
+
+    /// Returns the highlight of the char at `cx`
+    pub fn hl_at(&self, cx: usize) -> Highlight {
+        self.hl.get(cx).copied().unwrap_or_default()
+    }
+
+    fn starts_with_at(&self, i: usize, pat: &str) -> bool {
+        let mut chars = self.chars[i..].iter();
+        pat.chars().all(|c| chars.next() == Some(&c))
+    }
+
+    /// Returns the length of the char literal starting at `i`, eg. `'x'` or `'\''`.
+    /// Returns `None` for a `'` which doesn't start one, such as a lifetime.
+    fn char_literal_len(&self, i: usize) -> Option<usize> {
+        match self.chars.get(i + 1..)? {
+            // the longest escape is `\u{10FFFF}`
+            ['\\', rest @ ..] => rest
+                .iter()
+                .take(10)
+                .skip(1)
+                .position(|c| *c == '\'')
+                .map(|pos| pos + 4),
+            [c, '\'', ..] if *c != '\'' => Some(3),
+            _ => None,
+        }
+    }
+
+    /// Highlights the line using the state at the end of the previous line.
+    pub fn highlight(&mut self, syntax: &Syntax, start: HlState) {
+        let n = self.size();
+        self.hl.clear();
+        self.hl.resize(n, Highlight::Normal);
+        self.hl_start = Some(start);
+
+        let mut state = start;
+        if state == HlState::Normal {
+            let first = self.chars.iter().find(|c| !c.is_whitespace());
+            let line_hl = match first {
+                Some('#') if syntax.flags & HL_HEADINGS != 0 => Some(Highlight::Keyword1),
+                Some('[') if syntax.flags & HL_SECTIONS != 0 => Some(Highlight::Keyword2),
+                _ => None,
+            };
+            if let Some(hl) = line_hl {
+                self.hl.fill(hl);
+                self.hl_end = HlState::Normal;
+                return;
             }
-            cur_rx += 1;
+        }
 
-            if cur_rx > rx {
-                return cx;
+        let mut prev_sep = true;
+        let mut i = 0;
+
+        while i < n {
+            let c = self.chars[i];
+            let prev_hl = if i > 0 {
+                self.hl[i - 1]
+            } else {
+                Highlight::Normal
+            };
+
+            match state {
+                HlState::Comment => {
+                    let (_, end) = syntax.block_comment.unwrap_or_default();
+                    if self.starts_with_at(i, end) {
+                        let len = end.chars().count();
+                        self.hl[i..i + len].fill(Highlight::Comment);
+                        i += len;
+                        state = HlState::Normal;
+                        prev_sep = true;
+                    } else {
+                        self.hl[i] = Highlight::Comment;
+                        i += 1;
+                    }
+                    continue;
+                }
+                HlState::String(delim) => {
+                    self.hl[i] = Highlight::String;
+                    if c == '\\' && i + 1 < n && !syntax.raw_strings.contains(&delim) {
+                        self.hl[i + 1] = Highlight::String;
+                        i += 2;
+                    } else if self.starts_with_at(i, delim) {
+                        let len = delim.chars().count();
+                        self.hl[i..i + len].fill(Highlight::String);
+                        i += len;
+                        state = HlState::Normal;
+                        prev_sep = true;
+                    } else {
+                        i += 1;
+                    }
+                    continue;
+                }
+                HlState::Normal => {}
             }
-            cx += 1;
+
+            if let Some(lc) = syntax.line_comment {
+                if prev_sep && self.starts_with_at(i, lc) {
+                    self.hl[i..].fill(Highlight::Comment);
+                    break;
+                }
+            }
+
+            if let Some((start, _)) = syntax.block_comment {
+                if self.starts_with_at(i, start) {
+                    let len = start.chars().count();
+                    self.hl[i..i + len].fill(Highlight::Comment);
+                    i += len;
+                    state = HlState::Comment;
+                    continue;
+                }
+            }
+
+            if syntax.flags & HL_CHARS != 0 && c == '\'' {
+                if let Some(len) = self.char_literal_len(i) {
+                    self.hl[i..i + len].fill(Highlight::String);
+                    i += len;
+                    prev_sep = true;
+                    continue;
+                }
+            }
+
+            // multi-line delimiters are checked first since they can contain
+            // the single-line ones (eg. triple quotes in Python)
+            let delim = syntax
+                .multiline_strings
+                .iter()
+                .chain(syntax.strings)
+                .find(|d| self.starts_with_at(i, d));
+            if let Some(delim) = delim {
+                let len = delim.chars().count();
+                self.hl[i..i + len].fill(Highlight::String);
+                i += len;
+                state = HlState::String(delim);
+                continue;
+            }
+
+            if syntax.flags & HL_NUMBERS != 0
+                && ((c.is_ascii_digit() && (prev_sep || prev_hl == Highlight::Number))
+                    || (c == '.' && prev_hl == Highlight::Number))
+            {
+                self.hl[i] = Highlight::Number;
+                i += 1;
+                prev_sep = false;
+                continue;
+            }
+
+            if prev_sep {
+                let keyword = syntax
+                    .keywords
+                    .iter()
+                    .map(|k| (k, Highlight::Keyword1))
+                    .chain(syntax.types.iter().map(|k| (k, Highlight::Keyword2)))
+                    .find(|(k, _)| {
+                        let len = k.chars().count();
+                        self.starts_with_at(i, k)
+                            && self.chars.get(i + len).is_none_or(|c| is_separator(*c))
+                    });
+                if let Some((k, hl)) = keyword {
+                    let len = k.chars().count();
+                    self.hl[i..i + len].fill(hl);
+                    i += len;
+                    prev_sep = false;
+                    continue;
+                }
+            }
+
+            prev_sep = is_separator(c);
+            i += 1;
         }
-        cx
+
+        // strings which can't span multiple lines end along with the line
+        self.hl_end = match state {
+            HlState::String(delim) if !syntax.multiline_strings.contains(&delim) => HlState::Normal,
+            state => state,
+        };
     }
 }
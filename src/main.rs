@@ -2,29 +2,77 @@ use std::{
     cell::RefCell,
     cmp::min,
     env,
-    fmt::Display,
     fs::{self, File},
-    io::{self, BufRead, BufReader, BufWriter, Read, Stdout, Write},
+    io::{self, BufRead, BufReader, BufWriter, Stdout, Write},
+    ops::Range,
     path::Path,
     process::exit,
     rc::Rc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+mod clipboard;
+mod completion;
+mod config;
+mod filetype;
+mod history;
+mod input;
+mod key;
+mod keymap;
+mod killring;
 mod line;
+mod search;
+mod syntax;
 mod terminal;
-
-use line::Line;
-use terminal::{clear_screen, die, enable_raw_mode, get_window_size};
+mod undo;
+
+use completion::expand_home;
+use config::{Config, Options};
+use filetype::FileType;
+use history::History;
+use input::Input;
+use key::{ctrl_key, EditorKey, Modifiers, SpecialKey};
+use keymap::{keys_name, Command, Lookup};
+use killring::KillRing;
+use line::{is_separator, Highlight, HlState, Line};
+use search::{Matcher, SearchOptions};
+use terminal::{
+    clear_screen, die, enable_kitty_keyboard, enable_raw_mode, get_window_size, kitty_keyboard,
+    unread_byte, wait, wait_for_input, watch_resize, Wakeup,
+};
+use undo::{Edit, UndoStack};
+use unicode_width::UnicodeWidthStr;
 
 const KILO_VERSION: &str = "0.0.1";
+/// No. of lines scrolled by a turn of the mouse wheel
+const KILO_SCROLL_LINES: isize = 3;
+/// Max. time to wait for the end of a bracketed paste
+const KILO_PASTE_TIMEOUT: Duration = Duration::from_secs(5);
 
 struct Editor {
+    config: Config,
     screenrows: usize,
     screencols: usize,
     statusmsg: String,
     statusmsg_t: SystemTime,
     quit: bool,
+    /// Keys of a partially typed chord
+    pending_keys: Vec<EditorKey>,
+    search: SearchOptions,
+    /// Error in the pattern typed in the search prompt
+    search_error: Option<String>,
+    /// Matcher for the active search, its matches are highlighted on screen
+    search_matcher: Option<Matcher>,
+    /// (no. of the match under the cursor, total no. of matches) for the active search
+    search_count: Option<(Option<usize>, usize)>,
+    /// Screen column of the cursor in the message bar while a prompt is active
+    prompt_cursor: Option<usize>,
+    kill_ring: KillRing,
+    history: History,
+    /// Candidates found by the last path completion, listed in the message bar
+    completions: Option<Vec<String>>,
+    /// Candidate in the input while cycling through the completions
+    completion_index: Option<usize>,
     tabs: Vec<Rc<RefCell<Tab>>>,
     tab: Option<Rc<RefCell<Tab>>>,
     tab_index: usize,
@@ -43,124 +91,339 @@ struct Tab {
     col_offset: usize,
     rows: Vec<Line>,
     filename: Option<String>,
+    filetype: Option<&'static FileType>,
+    options: Options,
     dirty: usize,
-    last_match: i8,
-    direction: i8,
+    history: UndoStack,
+    /// Position (cx, cy) of the other end of the selection, the cursor being one end
+    mark: Option<(usize, usize)>,
+    /// Whether the selection was started with Shift + movement
+    shift_selection: bool,
+    /// Start and end (x, y) of the text pasted by the last command
+    last_paste: Option<((usize, usize), (usize, usize))>,
+    /// Position (cx, cy) of the match selected in the search prompt
+    last_match: Option<(usize, usize)>,
+    forward: bool,
 }
 
-const fn ctrl_key(k: char) -> char {
-    // when you press Ctrl in combination w/ other key in the terminal
-    // a modified character is sent w/ bits 5 and 6 stripped (set to '0')
-    // in the character corresponding to the key pressed
-    let v = (k as u8) & 0x1f;
-    v as char
+/// Input read from the terminal
+enum Event {
+    Key(EditorKey),
+    /// Text pasted while bracketed paste mode is on
+    Paste(String),
+    /// The terminal window was resized
+    Resize,
+    /// No input arrived before the timeout
+    Timeout,
 }
 
-#[derive(PartialEq, Clone, Copy)]
-enum EditorKey {
-    Char(char),
-    ArrowLeft,
-    ArrowRight,
-    ArrowUp,
-    ArrowDown,
-    PageUp,
-    PageDown,
-    Home,
-    End,
-    Delete,
-    Backspace,
+/// Reads a byte from stdin.
+///
+/// Returns an error of kind `UnexpectedEof` if no byte arrives within `escape_timeout`,
+/// used to tell if more bytes of an escape sequence follow.
+fn read_byte(escape_timeout: Duration) -> io::Result<u8> {
+    if !wait_for_input(escape_timeout)? {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    terminal::read_byte()
 }
 
-fn read_char() -> io::Result<char> {
-    let mut buf = [0; 1];
-    io::stdin().read_exact(&mut buf)?;
-    Ok(char::from(buf[0]))
+/// Reads a single UTF-8 encoded character from stdin.
+///
+/// Returns an error of kind `InvalidData` if the bytes read don't form a valid sequence.
+fn read_char(escape_timeout: Duration) -> io::Result<char> {
+    let b = read_byte(escape_timeout)?;
+    // the leading byte tells how many continuation bytes follow
+    let len = match b {
+        0x00..=0x7f => return Ok(char::from(b)),
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid utf-8 leading byte {:#x}", b),
+            ))
+        }
+    };
+
+    let mut buf = [b, 0, 0, 0];
+    for byte in buf.iter_mut().take(len).skip(1) {
+        // the rest of the sequence is sent together with the leading byte,
+        // so a timeout here means that the sequence was truncated
+        *byte = match read_byte(escape_timeout) {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "truncated utf-8 sequence",
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+    }
+
+    match std::str::from_utf8(&buf[..len]) {
+        Ok(s) => Ok(s.chars().next().unwrap()),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
 }
 
-fn editor_read_key() -> EditorKey {
+/// Waits for input, a resize or the timeout to expire.
+///
+/// A lone <esc> is the Esc key if nothing follows it within `escape_timeout`.
+fn read_event(timeout: Option<Duration>, escape_timeout: Duration) -> Event {
     let c = loop {
-        match read_char() {
+        match wait(timeout) {
+            Ok(Wakeup::Input) => {}
+            Ok(Wakeup::Resize) => return Event::Resize,
+            Ok(Wakeup::Timeout) => return Event::Timeout,
+            Err(e) => die("Failed to wait for input", e),
+        }
+        match read_char(escape_timeout) {
             Ok(c) => break c,
-            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                die("Failed to read from stdin", "end of input")
+            }
+            // drop malformed input instead of inserting garbage into the buffer
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {}
             Err(e) => die("Failed to read from stdin", e),
         }
     };
 
     if c == '\x1b' {
         // attempt to read the rest of the escape sequence
-        match read_char() {
-            Ok('[') => {
-                if let Ok(s1) = read_char() {
-                    match s1 {
-                        '1'..='9' => {
-                            if let Ok('~') = read_char() {
-                                match s1 {
-                                    '1' | '7' => return EditorKey::Home,
-                                    '3' => return EditorKey::Delete,
-                                    '4' | '8' => return EditorKey::End,
-                                    '5' => return EditorKey::PageUp,
-                                    '6' => return EditorKey::PageDown,
-                                    _ => {}
-                                }
-                            }
-                        }
-                        'A' => return EditorKey::ArrowUp,
-                        'B' => return EditorKey::ArrowDown,
-                        'C' => return EditorKey::ArrowRight,
-                        'D' => return EditorKey::ArrowLeft,
-                        'H' => return EditorKey::Home,
-                        'F' => return EditorKey::End,
-                        _ => {}
-                    }
-                }
-            }
-            Ok('O') => {
-                if let Ok(s1) = read_char() {
-                    match s1 {
-                        'H' => return EditorKey::Home,
-                        'F' => return EditorKey::End,
-                        _ => {}
-                    }
+        return match read_char(escape_timeout) {
+            Ok('[') => read_csi(timeout, escape_timeout),
+            Ok('O') => Event::Key(read_ss3(escape_timeout)),
+            // Alt + a special key is sent as <esc> followed by the key's sequence
+            Ok('\x1b') => match read_byte(escape_timeout) {
+                Ok(b'[') => match read_csi(timeout, escape_timeout) {
+                    Event::Key(key) => Event::Key(key.with_modifiers(Modifiers::ALT)),
+                    event => event,
+                },
+                Ok(b'O') => Event::Key(read_ss3(escape_timeout).with_modifiers(Modifiers::ALT)),
+                // Esc pressed twice, what follows is read as the next key
+                Ok(b) => {
+                    unread_byte(b);
+                    Event::Key(EditorKey::Char('\x1b'))
                 }
-            }
-            _ => {}
-        }
+                Err(_) => Event::Key(EditorKey::Char('\x1b')),
+            },
+            Ok('\x7f') => Event::Key(EditorKey::Modified(SpecialKey::Backspace, Modifiers::ALT)),
+            // terminals send Alt + key as <esc> followed by the key
+            Ok(c) => Event::Key(EditorKey::Alt(c)),
+            Err(_) => Event::Key(EditorKey::Char('\x1b')),
+        };
     }
 
-    if c as u8 == 127 {
+    if c == '\x7f' {
         // Even though in the ASCII table:
         // 127 is mapped to Delete and 8 is mapped to Backspace,
         // in modern computers the Backspace key is mapped to 127
         // and Delete key is mapped to <esc>[3~
-        return EditorKey::Backspace;
+        return Event::Key(EditorKey::Backspace);
     }
 
-    EditorKey::Char(c)
+    Event::Key(EditorKey::Char(c))
 }
 
-fn dyn_fmt<T: Display>(fmt_str: &str, args: &[T]) -> String {
-    let mut s = String::new();
-    for arg in args {
-        s = fmt_str.replacen("{}", &arg.to_string(), 1);
+/// Reads a control sequence following `<esc>[`
+fn read_csi(timeout: Option<Duration>, escape_timeout: Duration) -> Event {
+    let (params, last) = read_params(escape_timeout);
+    let key = if let Some(params) = params.strip_prefix('<') {
+        parse_mouse(params, last)
+    } else if params.starts_with(['?', '>', '=']) {
+        // replies to queries which arrived late, eg. `<esc>[?62c`
+        None
+    } else if last == Some('u') {
+        terminal::parse_kitty_key(&params)
+    } else if last == Some('~') && params == "200" {
+        return Event::Paste(read_paste(escape_timeout));
+    } else {
+        Some(parse_legacy_key(&params, last))
+    };
+    // events which aren't handled are skipped
+    match key {
+        Some(key) => Event::Key(key),
+        None => read_event(timeout, escape_timeout),
     }
-    s
 }
 
-const CTRL_F: char = ctrl_key('f');
+/// Parses a control sequence sent for a key in the legacy (xterm) encoding
+fn parse_legacy_key(params: &str, last: Option<char>) -> EditorKey {
+    let mut params = params.split(';');
+    let number = params.next().unwrap_or_default();
+    // the second parameter is 1 + a bitmask of the modifiers, eg. `<esc>[1;5C` is Ctrl + Right
+    let modifiers = Modifiers::from_param(params.next().unwrap_or_default());
+    // rxvt ends the sequence with `$`, `^` or `@` instead of `~` for Shift, Ctrl or both
+    let (last, modifiers) = match last {
+        Some('$') => (Some('~'), Modifiers::SHIFT),
+        Some('^') => (Some('~'), Modifiers::CTRL),
+        Some('@') => (Some('~'), Modifiers::CTRL | Modifiers::SHIFT),
+        last => (last, modifiers),
+    };
+
+    let key = match (last, number) {
+        (Some('~'), "1" | "7") => EditorKey::Home,
+        (Some('~'), "2") => EditorKey::Insert,
+        (Some('~'), "3") => EditorKey::Delete,
+        (Some('~'), "4" | "8") => EditorKey::End,
+        (Some('~'), "5") => EditorKey::PageUp,
+        (Some('~'), "6") => EditorKey::PageDown,
+        (Some('~'), "11") => EditorKey::F(1),
+        (Some('~'), "12") => EditorKey::F(2),
+        (Some('~'), "13") => EditorKey::F(3),
+        (Some('~'), "14") => EditorKey::F(4),
+        (Some('~'), "15") => EditorKey::F(5),
+        (Some('~'), "17") => EditorKey::F(6),
+        (Some('~'), "18") => EditorKey::F(7),
+        (Some('~'), "19") => EditorKey::F(8),
+        (Some('~'), "20") => EditorKey::F(9),
+        (Some('~'), "21") => EditorKey::F(10),
+        (Some('~'), "23") => EditorKey::F(11),
+        (Some('~'), "24") => EditorKey::F(12),
+        (Some('A'), _) => EditorKey::ArrowUp,
+        (Some('B'), _) => EditorKey::ArrowDown,
+        (Some('C'), _) => EditorKey::ArrowRight,
+        (Some('D'), _) => EditorKey::ArrowLeft,
+        (Some('H'), _) => EditorKey::Home,
+        (Some('F'), _) => EditorKey::End,
+        // rxvt sends Shift + arrows as lowercase letters
+        (Some('a'), _) => EditorKey::Modified(SpecialKey::Up, Modifiers::SHIFT),
+        (Some('b'), _) => EditorKey::Modified(SpecialKey::Down, Modifiers::SHIFT),
+        (Some('c'), _) => EditorKey::Modified(SpecialKey::Right, Modifiers::SHIFT),
+        (Some('d'), _) => EditorKey::Modified(SpecialKey::Left, Modifiers::SHIFT),
+        // F1-F4 along with modifiers, eg. `<esc>[1;2P`
+        (Some('P'), _) => EditorKey::F(1),
+        (Some('Q'), _) => EditorKey::F(2),
+        (Some('R'), _) => EditorKey::F(3),
+        (Some('S'), _) => EditorKey::F(4),
+        (Some('Z'), _) => EditorKey::ShiftTab,
+        _ => EditorKey::Char('\x1b'),
+    };
+    key.with_modifiers(modifiers)
+}
+
+/// Reads a key sent as `<esc>O` followed by a char
+fn read_ss3(escape_timeout: Duration) -> EditorKey {
+    match read_char(escape_timeout) {
+        Ok('A') => EditorKey::ArrowUp,
+        Ok('B') => EditorKey::ArrowDown,
+        Ok('C') => EditorKey::ArrowRight,
+        Ok('D') => EditorKey::ArrowLeft,
+        Ok('H') => EditorKey::Home,
+        Ok('F') => EditorKey::End,
+        Ok('P') => EditorKey::F(1),
+        Ok('Q') => EditorKey::F(2),
+        Ok('R') => EditorKey::F(3),
+        Ok('S') => EditorKey::F(4),
+        // rxvt sends Ctrl + arrows as lowercase letters
+        Ok('a') => EditorKey::Modified(SpecialKey::Up, Modifiers::CTRL),
+        Ok('b') => EditorKey::Modified(SpecialKey::Down, Modifiers::CTRL),
+        Ok('c') => EditorKey::Modified(SpecialKey::Right, Modifiers::CTRL),
+        Ok('d') => EditorKey::Modified(SpecialKey::Left, Modifiers::CTRL),
+        _ => EditorKey::Char('\x1b'),
+    }
+}
+
+/// Reads the parameters of an escape sequence along with the final char.
+///
+/// Parameters (numbers separated by `;`) are followed by a final char,
+/// eg. `<esc>[1;2C` is Shift + Right. They can also contain sub-parameters
+/// (separated by `:`) and start with a private marker (`<`, `=`, `>` or `?`).
+fn read_params(escape_timeout: Duration) -> (String, Option<char>) {
+    let mut params = String::new();
+    loop {
+        match read_char(escape_timeout) {
+            Ok(c @ '0'..='?') => params.push(c),
+            Ok(c) => return (params, Some(c)),
+            Err(_) => return (params, None),
+        }
+    }
+}
+
+/// Parses an SGR mouse report `<esc>[<button;x;yM` (`m` when the button is released)
+fn parse_mouse(params: &str, last: Option<char>) -> Option<EditorKey> {
+    let mut params = params.split(';').map(|p| p.parse::<usize>().ok());
+    let (Some(Some(button)), Some(Some(x)), Some(Some(y))) =
+        (params.next(), params.next(), params.next())
+    else {
+        return None;
+    };
+    // positions start from 1
+    let (x, y) = (x.saturating_sub(1), y.saturating_sub(1));
+    // bits 2-4 of the button are the Shift, Alt and Ctrl modifiers,
+    // 32 is added on movement and 64 for the wheel
+    match (button & !0b11100, last) {
+        (0, Some('M')) => Some(EditorKey::MouseDown(x, y)),
+        (32, Some('M')) => Some(EditorKey::MouseDrag(x, y)),
+        (64, Some('M')) => Some(EditorKey::ScrollUp),
+        (65, Some('M')) => Some(EditorKey::ScrollDown),
+        _ => None,
+    }
+}
+
+/// Reads text pasted in bracketed paste mode, up to the `<esc>[201~` marker.
+/// Line endings are converted to `\n`.
+///
+/// If the marker doesn't arrive in time (eg. the connection dropped),
+/// the text read so far is returned.
+fn read_paste(escape_timeout: Duration) -> String {
+    const END: &str = "\x1b[201~";
+    let deadline = Instant::now() + KILO_PASTE_TIMEOUT;
+    let mut text = String::new();
+    while !text.ends_with(END) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match wait_for_input(left) {
+            Ok(true) if !left.is_zero() => {}
+            Ok(_) => break,
+            Err(e) => die("Failed to read from stdin", e),
+        }
+        match read_char(escape_timeout) {
+            Ok(c) => text.push(c),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {}
+            Err(e) => die("Failed to read from stdin", e),
+        }
+    }
+    if text.ends_with(END) {
+        text.truncate(text.len() - END.len());
+    }
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
 const CTRL_H: char = ctrl_key('h');
 const CTRL_L: char = ctrl_key('l');
-const CTRL_Q: char = ctrl_key('q');
-const CTRL_T: char = ctrl_key('t');
-const CTRL_S: char = ctrl_key('s');
+const CTRL_N: char = ctrl_key('n');
+const CTRL_P: char = ctrl_key('p');
+const CTRL_R: char = ctrl_key('r');
+
+/// Terminals without the kitty keyboard protocol may send Ctrl-H for Backspace,
+/// so it's only a key of its own when keys are reported unambiguously.
+fn is_backspace(key: EditorKey) -> bool {
+    key == EditorKey::Backspace || (key == EditorKey::Char(CTRL_H) && !kitty_keyboard())
+}
 
 impl Editor {
-    fn new() -> Self {
+    fn new(config: Config) -> Self {
         Self {
+            config,
             screenrows: 0,
             screencols: 0,
             statusmsg: String::new(),
             statusmsg_t: UNIX_EPOCH,
             quit: false,
+            pending_keys: Vec::new(),
+            search: SearchOptions::default(),
+            search_error: None,
+            search_matcher: None,
+            search_count: None,
+            prompt_cursor: None,
+            kill_ring: KillRing::new(),
+            history: History::load(),
+            completions: None,
+            completion_index: None,
             tabs: Vec::new(),
             tab: None,
             tab_index: 0,
@@ -170,12 +433,24 @@ impl Editor {
     fn init(&mut self) -> io::Result<()> {
         (self.screenrows, self.screencols) = get_window_size()?;
         // assign 2 lines on the screen for the status bar
-        self.screenrows -= 2;
+        self.screenrows = self.screenrows.saturating_sub(2).max(1);
         Ok(())
     }
 
+    /// Updates the screen size of the editor and every tab after the terminal was resized
+    fn resize(&mut self) {
+        if let Err(e) = self.init() {
+            die("Failed to get window size", e)
+        };
+        for tab in &self.tabs {
+            tab.borrow_mut().resize(self.screenrows, self.screencols);
+        }
+        // the terminal may have left parts of the old screen in place
+        clear_screen();
+    }
+
     fn create_tab(&mut self) {
-        let tab = Tab::new(self.screenrows, self.screencols);
+        let tab = Tab::new(self.screenrows, self.screencols, self.config.options);
         self.tabs.push(Rc::new(RefCell::new(tab)));
         self.set_active_tab(self.tabs.len() - 1);
     }
@@ -185,6 +460,64 @@ impl Editor {
         self.tab_index = index;
     }
 
+    /// Returns the labels of the tabs shown in the status bar along with their columns.
+    /// The list is only shown when more than one tab is open.
+    fn tab_list(&self) -> Vec<(Range<usize>, String)> {
+        if self.tabs.len() < 2 {
+            return vec![];
+        }
+        let mut col = 0;
+        self.tabs
+            .iter()
+            .enumerate()
+            .map(|(i, tab)| {
+                let tab = tab.borrow();
+                let name = match tab
+                    .filename
+                    .as_deref()
+                    .and_then(|f| Path::new(f).file_name())
+                {
+                    Some(name) => name.to_string_lossy(),
+                    None => "[No Name]".into(),
+                };
+                let dirty = if tab.dirty > 0 { "+" } else { "" };
+                let label = format!(" {}:{:.20}{} ", i + 1, name, dirty);
+                let width = label.width();
+                col += width;
+                (col - width..col, label)
+            })
+            .collect()
+    }
+
+    /// Opens a file in a new tab, the file is created when it's saved if it doesn't exist
+    fn open_file(&mut self) {
+        let Some(fname) = self.prompt(
+            "Open: {} (ESC to cancel, Tab to complete)",
+            "open",
+            Some("complete-path"),
+        ) else {
+            self.set_status_message("Open aborted");
+            return;
+        };
+        let path = expand_home(&fname);
+
+        let mut tab = Tab::new(self.screenrows, self.screencols, self.config.options);
+        match tab.load_file(&path, &self.config) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                tab.filename = Some(path.to_string_lossy().into_owned());
+                tab.detect_filetype(&self.config);
+                self.set_status_message("New file");
+            }
+            Err(e) => {
+                self.set_status_message(&format!("Can't open! I/O error: {}", e));
+                return;
+            }
+        }
+        self.tabs.push(Rc::new(RefCell::new(tab)));
+        self.set_active_tab(self.tabs.len() - 1);
+    }
+
     fn save_file(&mut self) {
         let fname = {
             let fname = match self.tab.as_ref() {
@@ -194,9 +527,16 @@ impl Editor {
 
             match fname {
                 Some(fname) => fname,
-                None => match self.prompt("Save as: {} (ESC to cancel)", None) {
+                None => match self.prompt(
+                    "Save as: {} (ESC to cancel, Tab to complete)",
+                    "save-as",
+                    Some("complete-path"),
+                ) {
                     Some(fname) => {
-                        self.tab.as_ref().unwrap().borrow_mut().filename = Some(fname.clone());
+                        let fname = expand_home(&fname).to_string_lossy().into_owned();
+                        let mut tab = self.tab.as_ref().unwrap().borrow_mut();
+                        tab.filename = Some(fname.clone());
+                        tab.detect_filetype(&self.config);
                         fname
                     }
                     None => {
@@ -220,14 +560,16 @@ impl Editor {
         match fs::write(Path::new(&fname), &contents) {
             Ok(_) => {
                 self.set_status_message(&format!("{} bytes written to disk", contents.len()));
-                self.tab.as_ref().unwrap().borrow_mut().dirty = 0;
+                let mut tab = self.tab.as_ref().unwrap().borrow_mut();
+                tab.dirty = 0;
+                tab.history.mark_saved();
             }
             Err(e) => self.set_status_message(&format!("Can't save! I/O error: {}", e)),
         };
     }
 
     fn refresh_screen(&mut self) -> io::Result<()> {
-        let x = Rc::new(RefCell::new(Tab::new(0, 0))); // todo: improve
+        let x = Rc::new(RefCell::new(Tab::new(0, 0, self.config.options))); // todo: improve
 
         let tab = match self.tab.as_ref() {
             Some(v) => {
@@ -246,14 +588,15 @@ impl Editor {
         self.draw_rows(&mut w, &tab)?;
         self.draw_status_bar(&mut w, &tab)?;
 
-        w.write_all(
-            format!(
-                "\x1b[{};{}H",
-                (tab.cy - tab.row_offset) + 1,
-                (tab.rx - tab.col_offset) + 1
-            )
-            .as_bytes(),
-        )?;
+        let (y, x) = match self.prompt_cursor {
+            // the message bar is below the status bar
+            Some(x) => (self.screenrows + 1, x),
+            None => (
+                tab.cy - tab.row_offset,
+                (tab.rx - tab.col_offset) + tab.gutter_width(),
+            ),
+        };
+        w.write_all(format!("\x1b[{};{}H", y + 1, x + 1).as_bytes())?;
 
         // h cmd - Set mode
         w.write_all(b"\x1b[?25h")?; // show the cursor
@@ -266,7 +609,10 @@ impl Editor {
     fn draw_rows(&self, w: &mut BufWriter<Stdout>, tab: &Tab) -> io::Result<()> {
         let (rows, cols) = (self.screenrows, self.screencols);
         let numrows = tab.rows.len();
+        let gutter = tab.gutter_width();
+        let theme = self.config.theme;
         let (row_offset, col_offset) = (tab.row_offset, tab.col_offset);
+        let selection = tab.selection();
 
         for y in 0..rows {
             let filerow = y + row_offset;
@@ -286,11 +632,42 @@ impl Editor {
                     w.write_all(b"~")?;
                 }
             } else {
+                if gutter > 0 {
+                    w.write_all(theme.gutter().as_bytes())?;
+                    w.write_all(format!("{:>1$} ", filerow + 1, gutter - 1).as_bytes())?;
+                    w.write_all(theme.sgr(Highlight::Normal).as_bytes())?;
+                }
+
                 let r = &tab.rows[filerow];
-                let len = r.rsize().saturating_sub(col_offset).clamp(0, cols);
-                let start = if len == 0 { 0 } else { col_offset };
-                let end = start + len;
-                w.write_all(r.render[start..end].iter().collect::<String>().as_bytes())?;
+                let matches = match &self.search_matcher {
+                    Some(matcher) => tab.row_matches(matcher, filerow),
+                    None => vec![],
+                };
+                let mut current = Highlight::Normal;
+                for (cx, text) in r.visible(col_offset, cols.saturating_sub(gutter)) {
+                    let hl = match matches.iter().find(|m| m.contains(&cx)) {
+                        Some(m) if (m.start, filerow) == (tab.cx, tab.cy) => {
+                            Highlight::CurrentMatch
+                        }
+                        Some(_) => Highlight::Match,
+                        None => match selection {
+                            Some(((sx, sy), (ex, ey)))
+                                if (sy, sx) <= (filerow, cx) && (filerow, cx) < (ey, ex) =>
+                            {
+                                Highlight::Selection
+                            }
+                            _ => r.hl_at(cx),
+                        },
+                    };
+                    if hl != current {
+                        w.write_all(theme.sgr(hl).as_bytes())?;
+                        current = hl;
+                    }
+                    w.write_all(text.iter().collect::<String>().as_bytes())?;
+                }
+                if current != Highlight::Normal {
+                    w.write_all(theme.sgr(Highlight::Normal).as_bytes())?;
+                }
             }
 
             // K cmd - Erase in Line (erases part of current line)
@@ -306,27 +683,68 @@ impl Editor {
         // m cmd - Select Graphic Rendition
         // arg 7 corresponds to inverted colors
         w.write_all(b"\x1b[7m")?;
-        let fname = match &tab.filename {
-            Some(fname) => fname,
-            None => "[No Name]",
+        // the path can be long, only its last component is shown
+        let fname = match tab
+            .filename
+            .as_deref()
+            .and_then(|f| Path::new(f).file_name())
+        {
+            Some(fname) => fname.to_string_lossy(),
+            None => "[No Name]".into(),
         };
 
         let cols = self.screencols;
-        let status = format!(
-            "{:.20} - {} lines {}",
-            fname,
-            tab.rows.len(),
-            if tab.dirty > 0 { "(modified)" } else { "" }
+        let mut len = 0;
+        let tabs = self.tab_list();
+        for (i, (range, label)) in tabs.iter().enumerate() {
+            if range.end > cols {
+                break;
+            }
+            // arg 27 turns off the inverted colors for the current tab
+            if i == self.tab_index {
+                w.write_all(b"\x1b[27m")?;
+            }
+            w.write_all(label.as_bytes())?;
+            if i == self.tab_index {
+                w.write_all(b"\x1b[7m")?;
+            }
+            len = range.end;
+        }
+
+        // the tab list takes the place of the file name
+        let modified = if tab.dirty > 0 { "(modified)" } else { "" };
+        let status = if tabs.is_empty() {
+            format!("{:.20} - {} lines {}", fname, tab.rows.len(), modified)
+        } else {
+            format!("- {} lines {}", tab.rows.len(), modified)
+        };
+        let pending = if self.pending_keys.is_empty() {
+            String::new()
+        } else {
+            format!("{}- | ", keys_name(&self.pending_keys))
+        };
+        let rstatus = format!(
+            "{}{} | {}:{}",
+            pending,
+            tab.filetype.map_or("no ft", |ft| ft.name),
+            tab.cy + 1,
+            tab.cx + 1
         );
-        let mut len = min(cols, status.len());
-        let rstatus = format!("{}:{}", tab.cy + 1, tab.cx + 1);
         let rlen = rstatus.len();
 
-        w.write_all(status[..len].as_bytes())?;
+        // cut off at a char boundary, a multi-byte char mustn't be split
+        let end = status
+            .char_indices()
+            .map(|(i, c)| i + c.len_utf8())
+            .take_while(|&end| status[..end].width() <= cols - len)
+            .last()
+            .unwrap_or(0);
+        w.write_all(&status.as_bytes()[..end])?;
+        len += status[..end].width();
 
         while len < cols {
             if cols - len == rlen {
-                w.write_all(rstatus[..rlen].as_bytes())?;
+                w.write_all(&rstatus.as_bytes()[..rlen])?;
                 break;
             } else {
                 w.write_all(b" ")?;
@@ -340,80 +758,336 @@ impl Editor {
 
         // message_bar
         w.write_all(b"\x1b[K")?;
-        let msglen = min(self.statusmsg.len(), self.screencols);
-
-        if msglen > 0
+        if !self.statusmsg.is_empty()
             && SystemTime::now()
                 .duration_since(self.statusmsg_t)
                 .unwrap()
                 .as_secs()
-                < 5
+                < self.config.message_timeout
         {
-            w.write_all(self.statusmsg.as_bytes())?;
+            // cut off the part of the message which doesn't fit on the screen
+            let msg = Line::new(
+                self.statusmsg.chars().collect(),
+                self.config.options.tab_stop,
+            );
+            for (_, text) in msg.visible(0, self.screencols) {
+                w.write_all(text.iter().collect::<String>().as_bytes())?;
+            }
         }
 
         Ok(())
     }
 
     fn process_keypress(&mut self) {
-        match editor_read_key() {
-            EditorKey::Char(CTRL_Q) => {
+        let key = match read_event(self.message_expiry(), self.escape_timeout()) {
+            Event::Key(key) => key,
+            Event::Paste(text) => {
+                self.pending_keys.clear();
+                if let Some(v) = self.tab.as_ref() {
+                    let mut tab = v.borrow_mut();
+                    tab.paste(&text);
+                    // only text taken from the kill ring can be swapped by paste-older
+                    tab.last_paste = None;
+                }
+                self.quit = false;
+                return;
+            }
+            Event::Resize => {
+                self.resize();
+                return;
+            }
+            // the screen is redrawn without the expired status message
+            Event::Timeout => return,
+        };
+        if key.is_mouse() {
+            // the mouse isn't used in key sequences
+            self.pending_keys.clear();
+            self.quit = false;
+            self.process_mouse(key);
+            return;
+        }
+        self.pending_keys.push(key);
+
+        match self.config.keymap.lookup(&self.pending_keys) {
+            Lookup::Pending => return,
+            Lookup::Command(cmd) => {
+                self.pending_keys.clear();
+                self.run_command(cmd);
+                if cmd == Command::Quit {
+                    // keep the flag set so that pressing quit again exits
+                    return;
+                }
+            }
+            Lookup::Unbound if self.pending_keys.len() > 1 => {
+                // Esc silently cancels a partially typed chord
+                if key != EditorKey::Char('\x1b') {
+                    self.set_status_message(&format!(
+                        "{} is not bound",
+                        keys_name(&self.pending_keys)
+                    ));
+                }
+                self.pending_keys.clear();
+            }
+            Lookup::Unbound => {
+                self.pending_keys.clear();
+                if let Some(v) = self.tab.as_ref() {
+                    v.borrow_mut().process_buffer_keypress(key)
+                }
+            }
+        }
+
+        self.quit = false;
+    }
+
+    fn process_mouse(&mut self, key: EditorKey) {
+        match key {
+            // the status bar is right below the text, followed by the message bar
+            EditorKey::MouseDown(x, y) if y == self.screenrows => {
+                let tabs = self.tab_list();
+                let clicked = tabs
+                    .iter()
+                    .position(|(range, _)| range.contains(&x) && range.end <= self.screencols);
+                if let Some(i) = clicked {
+                    self.set_active_tab(i);
+                }
+            }
+            EditorKey::MouseDown(_, y) if y > self.screenrows => {}
+            _ => {
+                if let Some(v) = self.tab.as_ref() {
+                    v.borrow_mut().process_buffer_keypress(key)
+                }
+            }
+        }
+    }
+
+    fn run_command(&mut self, cmd: Command) {
+        if !matches!(cmd, Command::Paste | Command::PasteOlder) {
+            if let Some(v) = self.tab.as_ref() {
+                v.borrow_mut().last_paste = None;
+            }
+        }
+
+        match cmd {
+            Command::Quit => {
                 let dirty = &self.tabs.iter().any(|t| t.borrow().dirty > 0);
                 if *dirty && !self.quit {
-                    self.set_status_message(
-                        "WARNING!!! There are unsaved files. Press Ctrl-Q once more to quit.",
-                    );
+                    let keys = self
+                        .config
+                        .keymap
+                        .keys_for(Command::Quit)
+                        .unwrap_or_default();
+                    self.set_status_message(&format!(
+                        "WARNING!!! There are unsaved files. Press {} once more to quit.",
+                        keys
+                    ));
                     self.quit = true;
                     return;
                 }
                 clear_screen();
                 exit(0);
             }
-            EditorKey::Char(CTRL_T) => self.set_active_tab((self.tab_index + 1) % self.tabs.len()),
-            EditorKey::Char(CTRL_F) => self.find(),
-            EditorKey::Char(CTRL_S) => self.save_file(),
-            key => {
+            Command::NextTab if !self.tabs.is_empty() => {
+                self.set_active_tab((self.tab_index + 1) % self.tabs.len())
+            }
+            Command::PrevTab if !self.tabs.is_empty() => {
+                self.set_active_tab((self.tab_index + self.tabs.len() - 1) % self.tabs.len())
+            }
+            Command::Find => self.find(),
+            Command::Replace => self.replace(),
+            Command::Save => self.save_file(),
+            Command::Open => self.open_file(),
+            Command::Goto => self.goto(),
+            Command::Mark => {
                 if let Some(v) = self.tab.as_ref() {
-                    v.borrow_mut().process_buffer_keypress(key)
+                    let set = v.borrow_mut().toggle_mark();
+                    self.set_status_message(if set { "Mark set" } else { "Mark cleared" });
+                }
+            }
+            Command::Copy | Command::Cut => {
+                let Some(v) = self.tab.clone() else {
+                    return;
+                };
+                let mut tab = v.borrow_mut();
+                let selected = tab.selection().is_some();
+                let Some(text) = tab.kill(cmd == Command::Cut) else {
+                    return;
+                };
+                let result = clipboard::copy(&self.config, &text);
+                self.kill_ring.push(text);
+                match result {
+                    Ok(()) => self.set_status_message(&format!(
+                        "{} {}",
+                        if cmd == Command::Cut { "Cut" } else { "Copied" },
+                        if selected { "selection" } else { "line" }
+                    )),
+                    Err(e) => self.set_status_message(&format!("Can't copy to clipboard: {}", e)),
+                }
+            }
+            Command::Paste | Command::PasteOlder => {
+                let Some(v) = self.tab.clone() else {
+                    return;
+                };
+                if cmd == Command::Paste {
+                    // text copied in other programs takes precedence
+                    match clipboard::paste(&self.config) {
+                        Ok(Some(text)) if self.kill_ring.yank() != Some(&text) => {
+                            self.kill_ring.push(text)
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            self.set_status_message(&format!("Can't paste from clipboard: {}", e))
+                        }
+                    }
+                }
+                let mut tab = v.borrow_mut();
+                let text = if cmd == Command::Paste {
+                    self.kill_ring.yank()
+                } else if let Some((start, end)) = tab.last_paste {
+                    // swap the text pasted last for an older kill, as a single undo step
+                    tab.history.begin_group();
+                    tab.mark = Some(start);
+                    (tab.cx, tab.cy) = end;
+                    tab.delete_selection();
+                    self.kill_ring.yank_older()
+                } else {
+                    drop(tab);
+                    self.set_status_message("Nothing was pasted just before");
+                    return;
+                };
+                let pasted = text.map(|text| tab.paste(text)).is_some();
+                if cmd == Command::PasteOlder {
+                    tab.history.end_group();
+                }
+                drop(tab);
+                if !pasted {
+                    self.set_status_message("Nothing to paste");
+                }
+            }
+            Command::Undo => {
+                if let Some(v) = self.tab.as_ref() {
+                    v.borrow_mut().undo()
                 }
             }
+            Command::Redo => {
+                if let Some(v) = self.tab.as_ref() {
+                    v.borrow_mut().redo()
+                }
+            }
+            Command::NextTab | Command::PrevTab => {}
         }
-
-        self.quit = false;
     }
 
-    #[allow(clippy::option_map_unit_fn)]
-    fn prompt(&mut self, prompt: &str, callback: Option<&str>) -> Option<String> {
-        let mut buf = String::new();
+    /// Reads a line of input in the message bar.
+    /// `prompt` is shown with `{}` replaced by the input.
+    /// Up/Down go through the earlier inputs of prompts of the same `kind`.
+    ///
+    /// The callback is run for every key which changes the input or isn't a line editing key,
+    /// it can replace the input by returning its new contents.
+    fn prompt(&mut self, prompt: &str, kind: &str, callback: Option<&str>) -> Option<String> {
+        let (prefix, suffix) = prompt.split_once("{}").unwrap_or((prompt, ""));
+        let prefix_width = prefix.width();
+        let mut input = Input::new();
+        // position in the history, the input being typed is right after the last entry
+        let mut history_index = self.history.get(kind).len();
+        let mut draft = String::new();
 
         loop {
-            let msg = dyn_fmt(prompt, &[&buf]);
+            // the input scrolls horizontally when it doesn't fit next to the prefix
+            let width = self.screencols.saturating_sub(prefix_width + 1).max(1);
+            let (text, cursor) = input.render(width);
+            let mut msg = format!("{}{}{}", prefix, text, suffix);
+            if let Some(status) = callback.and_then(|cb| self.callback_status(cb)) {
+                msg = format!("{} {}", msg, status);
+            }
             self.set_status_message(&msg);
+            self.prompt_cursor = Some(prefix_width + cursor);
             self.refresh_screen().unwrap();
 
-            let ch = editor_read_key();
-
-            match ch {
-                EditorKey::Delete | EditorKey::Backspace | EditorKey::Char(CTRL_H) => {
-                    if !buf.is_empty() {
-                        buf.pop();
+            let buf = input.text();
+            let ch = match read_event(None, self.escape_timeout()) {
+                Event::Key(key) => key,
+                Event::Paste(text) => {
+                    input.insert(&text);
+                    // the callback sees the paste as its last char being typed
+                    match text.chars().rev().find(|c| !c.is_control()) {
+                        Some(c) if input.text() != buf => EditorKey::Char(c),
+                        _ => continue,
                     }
                 }
+                Event::Resize => {
+                    self.resize();
+                    continue;
+                }
+                Event::Timeout => continue,
+            };
+
+            match ch {
+                // pasted text is already inserted
+                _ if input.text() != buf => {}
+                _ if ch.is_mouse() => continue,
                 EditorKey::Char('\x1b') => {
                     self.set_status_message("");
-                    callback.map(|cb| self.run_callback(cb, &buf, ch));
+                    self.prompt_cursor = None;
+                    if let Some(cb) = callback {
+                        self.run_callback(cb, &buf, ch);
+                    }
                     return None;
                 }
                 EditorKey::Char('\r') => {
                     self.set_status_message("");
-                    callback.map(|cb| self.run_callback(cb, &buf, ch));
+                    self.prompt_cursor = None;
+                    if let Some(cb) = callback {
+                        self.run_callback(cb, &buf, ch);
+                    }
+                    self.history.push(kind, &buf);
+                    if let Err(e) = self.history.save() {
+                        self.set_status_message(&format!("Can't save history! I/O error: {}", e));
+                    }
                     return Some(buf);
                 }
-                EditorKey::Char(c) if !c.is_control() => buf.push(c),
-                _ => {}
+                EditorKey::ArrowUp | EditorKey::ArrowDown => {
+                    let entries = self.history.get(kind);
+                    if history_index == entries.len() {
+                        draft = buf.clone();
+                    }
+                    history_index = match ch {
+                        EditorKey::ArrowUp => history_index.saturating_sub(1),
+                        _ => min(history_index + 1, entries.len()),
+                    };
+                    input.set_text(entries.get(history_index).unwrap_or(&draft));
+                    if input.text() == buf {
+                        continue;
+                    }
+                }
+                _ => {
+                    // cursor movement is of no interest to the callback
+                    if input.process_key(ch, &mut self.kill_ring) && input.text() == buf {
+                        continue;
+                    }
+                }
             };
 
-            callback.map(|cb| self.run_callback(cb, &buf, ch));
+            if let Some(text) = callback.and_then(|cb| self.run_callback(cb, &input.text(), ch)) {
+                input.set_text(&text);
+            }
+        }
+    }
+
+    fn goto(&mut self) {
+        let Some(tab) = self.tab.clone() else {
+            return;
+        };
+        let Some(target) = self.prompt(
+            "Go to: {} (line[:col], +N/-N lines, N%, bN byte offset)",
+            "goto",
+            None,
+        ) else {
+            return;
+        };
+
+        let result = tab.borrow_mut().goto(&target);
+        if let Err(e) = result {
+            self.set_status_message(&e);
         }
     }
 
@@ -426,8 +1100,13 @@ impl Editor {
             None => return,
         };
 
+        self.search_error = None;
         if self
-            .prompt("Search: {} (ESC/Arrows/Enter)", Some("find"))
+            .prompt(
+                "Search: {} (ESC/Enter, Ctrl-N/P: next/prev, Alt-R/C/W: regex/case/word)",
+                "search",
+                Some("find"),
+            )
             .is_none()
         {
             let mut tab = self.tab.as_ref().unwrap().borrow_mut();
@@ -436,11 +1115,230 @@ impl Editor {
         };
     }
 
-    fn run_callback(&self, callback_name: &str, query: &str, key: EditorKey) {
-        if let "find" = callback_name {
-            if let Some(t) = self.tab.as_ref() {
-                t.borrow_mut().find_cb(query, key)
+    fn replace(&mut self) {
+        let Some(tab) = self.tab.clone() else {
+            return;
+        };
+        let (cx, cy, coloff, rowoff, mark) = {
+            let tab = tab.borrow();
+            (tab.cx, tab.cy, tab.col_offset, tab.row_offset, tab.mark)
+        };
+        let restore_cursor = || {
+            let mut tab = tab.borrow_mut();
+            (tab.cx, tab.cy) = (cx, cy);
+            (tab.col_offset, tab.row_offset) = (coloff, rowoff);
+            tab.mark = mark;
+        };
+        // the selection or else the whole buffer,
+        // taken before the search moves the cursor
+        let mut range = match tab.borrow().selection() {
+            Some((start, end)) => start..end,
+            None => (0, 0)..(0, tab.borrow().rows.len()),
+        };
+        tab.borrow_mut().mark = None;
+
+        self.search_error = None;
+        let query = self.prompt(
+            "Replace: {} (ESC/Enter, Ctrl-N/P: next/prev, Alt-R/C/W: regex/case/word)",
+            "search",
+            Some("find"),
+        );
+        let matcher = match query.as_deref().map(|q| Matcher::new(q, self.search)) {
+            Some(Ok(matcher)) if !query.as_ref().unwrap().is_empty() => matcher,
+            Some(Err(e)) => {
+                restore_cursor();
+                self.set_status_message(&format!("Invalid pattern: {}", e));
+                return;
+            }
+            _ => {
+                restore_cursor();
+                self.set_status_message("Replace aborted");
+                return;
+            }
+        };
+        let Some(template) = self.prompt("Replace with: {} (ESC to cancel)", "replace", None)
+        else {
+            restore_cursor();
+            self.set_status_message("Replace aborted");
+            return;
+        };
+
+        let mut count = 0;
+        let mut confirm = true;
+        let (mut x, mut y) = range.start;
+
+        self.search_matcher = Some(matcher.clone());
+        tab.borrow_mut().history.begin_group();
+        loop {
+            let Some((my, m, s)) = tab.borrow().find_from(&matcher, x, y, range.end) else {
+                break;
+            };
+            // convert the byte offsets into char indexes
+            let start = s[..m.start].chars().count();
+            let end = start + s[m.clone()].chars().count();
+
+            if confirm {
+                {
+                    let mut tab = tab.borrow_mut();
+                    (tab.cx, tab.cy) = (start, my);
+                }
+                self.set_status_message("Replace this occurrence? (y)es (n)o (a)ll (q)uit");
+                self.refresh_screen().unwrap();
+
+                match read_event(None, self.escape_timeout()) {
+                    Event::Key(EditorKey::Char('y')) => {}
+                    Event::Key(EditorKey::Char('n')) => {
+                        // skip over the match, and the next char in case of an empty match
+                        (x, y) = (if start == end { end + 1 } else { end }, my);
+                        continue;
+                    }
+                    Event::Key(EditorKey::Char('a')) => confirm = false,
+                    Event::Key(EditorKey::Char('q') | EditorKey::Char('\x1b')) => break,
+                    Event::Resize => {
+                        self.resize();
+                        continue;
+                    }
+                    _ => continue,
+                }
+            }
+
+            let replacement = matcher
+                .expand(&s, &m, &template)
+                .chars()
+                .collect::<Vec<_>>();
+            let len = replacement.len();
+            tab.borrow_mut().replace_text(my, start..end, replacement);
+            if my == range.end.1 {
+                // the end of the selection moves along with the text after the match
+                range.end.0 = range.end.0 + len - (end - start);
+            }
+            count += 1;
+            (x, y) = (
+                if start == end {
+                    start + len + 1
+                } else {
+                    start + len
+                },
+                my,
+            );
+        }
+        tab.borrow_mut().history.end_group();
+        self.search_matcher = None;
+
+        self.set_status_message(&format!(
+            "Replaced {} occurrence{}",
+            count,
+            if count == 1 { "" } else { "s" }
+        ));
+    }
+
+    /// Returns the new contents of the input if the callback changes it
+    fn run_callback(&mut self, callback_name: &str, query: &str, key: EditorKey) -> Option<String> {
+        match callback_name {
+            "find" => {
+                self.find_callback(query, key);
+                None
+            }
+            "complete-path" => self.complete_path_callback(query, key),
+            _ => None,
+        }
+    }
+
+    fn find_callback(&mut self, query: &str, key: EditorKey) {
+        match key {
+            EditorKey::Char(CTRL_R) | EditorKey::Alt('r') => self.search.regex = !self.search.regex,
+            EditorKey::Alt('c') => self.search.cycle_case(),
+            EditorKey::Alt('w') => self.search.whole_word = !self.search.whole_word,
+            _ => {}
+        }
+
+        self.search_error = None;
+        self.search_matcher = None;
+        self.search_count = None;
+        let matcher = match Matcher::new(query, self.search) {
+            Ok(matcher) => matcher,
+            Err(e) => {
+                self.search_error = Some(e);
+                return;
             }
+        };
+        let Some(t) = self.tab.as_ref() else {
+            return;
+        };
+
+        let mut tab = t.borrow_mut();
+        tab.find_cb(&matcher, key);
+        if !matches!(key, EditorKey::Char('\r') | EditorKey::Char('\x1b')) && !query.is_empty() {
+            self.search_count = Some(tab.match_count(&matcher));
+            self.search_matcher = Some(matcher);
+        }
+    }
+
+    /// Completes the path typed in the input when Tab is pressed.
+    ///
+    /// The input is completed up to the longest common prefix of the candidates,
+    /// pressing Tab again cycles through them.
+    fn complete_path_callback(&mut self, query: &str, key: EditorKey) -> Option<String> {
+        if key != EditorKey::Char('\t') {
+            self.completions = None;
+            return None;
+        }
+
+        if let Some(candidates) = self.completions.as_ref().filter(|c| c.len() > 1) {
+            let i = self
+                .completion_index
+                .map_or(0, |i| (i + 1) % candidates.len());
+            self.completion_index = Some(i);
+            return Some(candidates[i].clone());
+        }
+
+        let candidates = completion::complete_path(query);
+        let completed = match candidates.len() {
+            0 => None,
+            1 => Some(candidates[0].clone()),
+            _ => Some(completion::common_prefix(&candidates)),
+        };
+        self.completion_index = None;
+        // a single candidate isn't kept, so that the next Tab completes a directory's contents
+        self.completions = (candidates.len() != 1).then_some(candidates);
+        completed
+    }
+
+    /// Returns extra info shown after the prompt while the callback is active
+    fn callback_status(&self, callback_name: &str) -> Option<String> {
+        match callback_name {
+            "find" => {
+                let status = match (&self.search_error, self.search_count) {
+                    (Some(e), _) => format!("Invalid pattern: {}", e),
+                    (None, Some((_, 0))) => "No matches".to_owned(),
+                    (None, Some((Some(i), total))) => format!("{}/{}", i + 1, total),
+                    (None, Some((None, total))) => format!("{} matches", total),
+                    (None, None) => String::new(),
+                };
+                let status = [self.search.label(), status]
+                    .into_iter()
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                (!status.is_empty()).then_some(status)
+            }
+            "complete-path" => match self.completions.as_deref()? {
+                [] => Some("No matches".to_owned()),
+                candidates => {
+                    // only the last component of the paths
+                    let names = candidates.iter().enumerate().map(|(i, c)| {
+                        let name = c.trim_end_matches('/').rsplit('/').next().unwrap_or(c);
+                        let name = format!("{}{}", name, if c.ends_with('/') { "/" } else { "" });
+                        if Some(i) == self.completion_index {
+                            format!("[{}]", name)
+                        } else {
+                            name
+                        }
+                    });
+                    Some(format!("{{{}}}", names.collect::<Vec<_>>().join(" ")))
+                }
+            },
+            _ => None,
         }
     }
 
@@ -448,10 +1346,24 @@ impl Editor {
         self.statusmsg = msg.to_owned();
         self.statusmsg_t = SystemTime::now();
     }
+
+    /// Returns the time left until the status message expires, if one is shown
+    /// Max. time to wait for the rest of an escape sequence
+    fn escape_timeout(&self) -> Duration {
+        Duration::from_millis(self.config.escape_timeout)
+    }
+
+    fn message_expiry(&self) -> Option<Duration> {
+        if self.statusmsg.is_empty() {
+            return None;
+        }
+        let shown = SystemTime::now().duration_since(self.statusmsg_t).ok()?;
+        Duration::from_secs(self.config.message_timeout).checked_sub(shown)
+    }
 }
 
 impl Tab {
-    fn new(screenrows: usize, screencols: usize) -> Self {
+    fn new(screenrows: usize, screencols: usize, options: Options) -> Self {
         Self {
             screenrows,
             screencols,
@@ -462,33 +1374,153 @@ impl Tab {
             col_offset: 0,
             rows: Vec::new(),
             filename: None,
+            filetype: None,
+            options,
             dirty: 0,
-            last_match: -1,
-            direction: 1,
+            history: UndoStack::new(),
+            mark: None,
+            shift_selection: false,
+            last_paste: None,
+            last_match: None,
+            forward: true,
         }
     }
 
-    fn load_file(&mut self, path: &Path) {
-        let file = match File::open(path) {
-            Ok(file) => file,
-            Err(e) => die("Could not open file", e),
-        };
-        self.filename = path
-            .file_name()
-            .map(|os_str| os_str.to_str().unwrap().to_owned());
+    fn load_file(&mut self, path: &Path, config: &Config) -> io::Result<()> {
+        let file = File::open(path)?;
+        self.filename = Some(path.to_string_lossy().into_owned());
         let reader = BufReader::new(file);
-        for (i, line) in reader.lines().enumerate() {
-            self.rows.push(Line {
-                chars: line.unwrap().chars().collect(),
-                render: vec![],
-            });
-            self.rows[i].update();
+        for line in reader.lines() {
+            self.rows
+                .push(Line::new(line?.chars().collect(), self.options.tab_stop));
         }
+        self.detect_filetype(config);
+        Ok(())
     }
 
     fn process_buffer_keypress(&mut self, key: EditorKey) {
+        self.last_paste = None;
+        if !matches!(
+            key,
+            EditorKey::Char(_) | EditorKey::Delete | EditorKey::Backspace
+        ) {
+            // moving the cursor ends the current run of typing
+            self.history.seal();
+        }
+
+        if let Some(movement) = key.unshifted() {
+            // Shift + movement starts or extends the selection
+            if self.mark.is_none() {
+                self.mark = Some((self.cx, self.cy));
+                self.shift_selection = true;
+            }
+            self.move_by(movement);
+            return;
+        }
+
+        let key = if is_backspace(key) {
+            EditorKey::Backspace
+        } else {
+            key
+        };
+        match key {
+            EditorKey::PageUp
+            | EditorKey::PageDown
+            | EditorKey::ArrowUp
+            | EditorKey::ArrowDown
+            | EditorKey::ArrowLeft
+            | EditorKey::ArrowRight
+            | EditorKey::Home
+            | EditorKey::End
+            | EditorKey::Modified(
+                SpecialKey::Left
+                | SpecialKey::Right
+                | SpecialKey::Up
+                | SpecialKey::Down
+                | SpecialKey::Home
+                | SpecialKey::End,
+                Modifiers::CTRL,
+            ) => {
+                // a selection made with Shift ends when moving without it
+                if self.shift_selection {
+                    self.clear_mark();
+                }
+                self.move_by(key);
+            }
+            EditorKey::Char('\r') => self.replace_selection(Self::insert_newline),
+            c @ (EditorKey::Delete | EditorKey::Backspace) => {
+                if self.delete_selection() {
+                    return;
+                }
+                // Delete is triggered through fn + delete on the Mac keyboard
+                if c == EditorKey::Delete {
+                    self.del_char_forward();
+                } else {
+                    self.del_char();
+                }
+            }
+            EditorKey::Char('\x1b') => self.clear_mark(),
+            EditorKey::MouseDown(x, y) => {
+                self.clear_mark();
+                self.move_to_screen(x, y);
+            }
+            EditorKey::MouseDrag(x, y) => {
+                // dragging selects the text from where the button was pressed
+                if self.mark.is_none() {
+                    self.mark = Some((self.cx, self.cy));
+                    self.shift_selection = true;
+                }
+                self.move_to_screen(x, y);
+            }
+            EditorKey::ScrollUp => self.scroll_by(-KILO_SCROLL_LINES),
+            EditorKey::ScrollDown => self.scroll_by(KILO_SCROLL_LINES),
+            EditorKey::Char(CTRL_L) | EditorKey::Alt(_) => {}
+            EditorKey::Char('\t') if self.selection().is_some() => self.indent_selection(false),
+            EditorKey::ShiftTab => self.indent_selection(true),
+            EditorKey::Char('\t') if self.options.expand_tabs => {
+                // fill up to the next tab stop with spaces
+                let rx = match self.rows.get(self.cy) {
+                    Some(row) => row.cx_to_rx(self.cx),
+                    None => 0,
+                };
+                for _ in 0..self.options.tab_stop - rx % self.options.tab_stop {
+                    self.insert_char(' ');
+                }
+            }
+            // control chars which aren't bound to anything
+            EditorKey::Char(c) if c.is_control() && c != '\t' => {}
+            EditorKey::Char(c) => self.replace_selection(|tab| tab.insert_char(c)),
+            EditorKey::Modified(..) | EditorKey::Ctrl(_) | EditorKey::Insert | EditorKey::F(_) => {}
+        }
+    }
+
+    /// Moves the cursor to the char shown at the screen position (x, y)
+    fn move_to_screen(&mut self, x: usize, y: usize) {
+        self.cy = min(self.row_offset + y, self.rows.len());
+        let rx = x.saturating_sub(self.gutter_width()) + self.col_offset;
+        self.cx = self.rows.get(self.cy).map_or(0, |row| row.rx_to_cx(rx));
+    }
+
+    /// Scrolls the view by a no. of lines, moving the cursor along if it would go off screen
+    fn scroll_by(&mut self, lines: isize) {
+        let last = self.rows.len().saturating_sub(1);
+        self.row_offset = min(self.row_offset.saturating_add_signed(lines), last);
+
+        let bottom = self.row_offset + self.screenrows.saturating_sub(1);
+        let cy = self.cy.clamp(self.row_offset, bottom);
+        if cy != self.cy {
+            // keep the cursor on the same screen column, as when moving b/w lines
+            let rx = self
+                .rows
+                .get(self.cy)
+                .map_or(0, |row| row.cx_to_rx(self.cx));
+            self.cy = min(cy, self.rows.len());
+            self.cx = self.rows.get(self.cy).map_or(0, |row| row.rx_to_cx(rx));
+        }
+    }
+
+    fn move_by(&mut self, key: EditorKey) {
         match key {
-            EditorKey::Char('\r') => self.insert_newline(),
             c @ (EditorKey::PageUp | EditorKey::PageDown) => {
                 if c == EditorKey::PageUp {
                     self.cy = self.row_offset
@@ -510,26 +1542,69 @@ impl Tab {
                     self.move_cursor(movement);
                 }
             }
-            c @ (EditorKey::ArrowUp
-            | EditorKey::ArrowDown
-            | EditorKey::ArrowLeft
-            | EditorKey::ArrowRight) => self.move_cursor(c),
             EditorKey::Home => self.cx = 0,
             EditorKey::End => {
                 if self.cy < self.rows.len() {
                     self.cx = self.rows[self.cy].size();
                 }
             }
-            c @ (EditorKey::Delete | EditorKey::Backspace | EditorKey::Char(CTRL_H)) => {
-                // Delete is triggered through fn + delete on the Mac keyboard
-                if c == EditorKey::Delete {
-                    self.move_cursor(EditorKey::ArrowRight);
+            EditorKey::Modified(SpecialKey::Left, Modifiers::CTRL) => self.move_word(false),
+            EditorKey::Modified(SpecialKey::Right, Modifiers::CTRL) => self.move_word(true),
+            EditorKey::Modified(SpecialKey::Up, Modifiers::CTRL) => self.scroll_by(-1),
+            EditorKey::Modified(SpecialKey::Down, Modifiers::CTRL) => self.scroll_by(1),
+            EditorKey::Modified(SpecialKey::Home, Modifiers::CTRL) => (self.cx, self.cy) = (0, 0),
+            EditorKey::Modified(SpecialKey::End, Modifiers::CTRL) => {
+                (self.cx, self.cy) = self.clamp_pos((0, self.rows.len()));
+            }
+            c => self.move_cursor(c),
+        }
+    }
+
+    /// Moves the cursor to the start of the previous word or the end of the next one,
+    /// going across lines
+    fn move_word(&mut self, forward: bool) {
+        let is_word = |row: &Line, x: usize| !is_separator(row.chars[x]);
+        if self.cy >= self.rows.len() {
+            if forward || self.rows.is_empty() {
+                return;
+            }
+            (self.cx, self.cy) = self.clamp_pos((0, self.rows.len()));
+        }
+
+        // skip the separators (and line breaks) before the word
+        loop {
+            let row = &self.rows[self.cy];
+            if forward {
+                while self.cx < row.size() && !is_word(row, self.cx) {
+                    self.cx += 1;
                 }
-                self.del_char();
+                if self.cx < row.size() || self.cy + 1 == self.rows.len() {
+                    break;
+                }
+                (self.cx, self.cy) = (0, self.cy + 1);
+            } else {
+                while self.cx > 0 && !is_word(row, self.cx - 1) {
+                    self.cx -= 1;
+                }
+                if self.cx > 0 || self.cy == 0 {
+                    break;
+                }
+                self.cy -= 1;
+                self.cx = self.rows[self.cy].size();
+            }
+        }
+
+        let row = &self.rows[self.cy];
+        if forward {
+            while self.cx < row.size() && is_word(row, self.cx) {
+                self.cx += 1;
+            }
+        } else {
+            while self.cx > 0 && is_word(row, self.cx - 1) {
+                self.cx -= 1;
             }
-            EditorKey::Char('\x1b') | EditorKey::Char(CTRL_L) => {}
-            EditorKey::Char(c) => self.insert_char(c),
         }
+        self.cx = row.snap_cx(self.cx);
     }
 
     fn move_cursor(&mut self, key: EditorKey) {
@@ -542,7 +1617,7 @@ impl Tab {
         match key {
             EditorKey::ArrowLeft => {
                 if self.cx != 0 {
-                    self.cx -= 1
+                    self.cx = self.rows[self.cy].prev_cx(self.cx)
                 } else if self.cy > 0 {
                     self.cy -= 1;
                     self.cx = self.rows[self.cy].size();
@@ -551,7 +1626,7 @@ impl Tab {
             EditorKey::ArrowRight => {
                 if let Some(row) = row {
                     match self.cx.cmp(&row.size()) {
-                        std::cmp::Ordering::Less => self.cx += 1,
+                        std::cmp::Ordering::Less => self.cx = row.next_cx(self.cx),
                         std::cmp::Ordering::Equal => {
                             self.cy += 1;
                             self.cx = 0;
@@ -560,8 +1635,18 @@ impl Tab {
                     }
                 }
             }
-            EditorKey::ArrowUp => self.cy = self.cy.saturating_sub(1),
-            EditorKey::ArrowDown if self.cy < self.rows.len() => self.cy += 1,
+            EditorKey::ArrowUp | EditorKey::ArrowDown => {
+                // keep the cursor on the same screen column when moving b/w lines
+                let rx = row.map_or(0, |row| row.cx_to_rx(self.cx));
+                if key == EditorKey::ArrowUp {
+                    self.cy = self.cy.saturating_sub(1);
+                } else if self.cy < self.rows.len() {
+                    self.cy += 1;
+                }
+                if self.cy < self.rows.len() {
+                    self.cx = self.rows[self.cy].rx_to_cx(rx);
+                }
+            }
             _ => {}
         }
 
@@ -576,19 +1661,95 @@ impl Tab {
         if self.cx > rowlen {
             self.cx = rowlen;
         }
+        // don't leave the cursor in the middle of a grapheme
+        if let Some(row) = row {
+            self.cx = row.snap_cx(self.cx);
+        }
+    }
+
+    /// Moves the cursor to `target` and centers it on the screen.
+    ///
+    /// The target can be a `line` or `line:col` (starting from 1), a no. of lines
+    /// relative to the cursor (`+N`/`-N`), a percentage of the file (`N%`) or a byte offset (`bN`).
+    fn goto(&mut self, target: &str) -> Result<(), String> {
+        let target = target.trim();
+        let parse = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| format!("Invalid position '{}'", target))
+        };
+        let last = self.rows.len().saturating_sub(1);
+
+        let (cx, cy) = if let Some(n) = target.strip_prefix('+') {
+            (self.cx, self.cy.saturating_add(parse(n)?))
+        } else if let Some(n) = target.strip_prefix('-') {
+            (self.cx, self.cy.saturating_sub(parse(n)?))
+        } else if let Some(n) = target.strip_suffix('%') {
+            let line = (min(parse(n)?, 100) * self.rows.len()).div_ceil(100);
+            (0, line.saturating_sub(1))
+        } else if let Some(n) = target.strip_prefix('b') {
+            self.offset_to_pos(parse(n)?)
+        } else {
+            let (line, col) = target.split_once(':').unwrap_or((target, "1"));
+            (
+                parse(col)?.saturating_sub(1),
+                parse(line)?.saturating_sub(1),
+            )
+        };
+
+        self.cy = min(cy, last);
+        self.cx = match self.rows.get(self.cy) {
+            Some(row) => row.snap_cx(min(cx, row.size())),
+            None => 0,
+        };
+        self.row_offset = self.cy.saturating_sub(self.screenrows / 2);
+        self.history.seal();
+        Ok(())
+    }
+
+    /// Converts a byte offset in the file into (cx, cy)
+    fn offset_to_pos(&self, mut offset: usize) -> (usize, usize) {
+        for (y, row) in self.rows.iter().enumerate() {
+            let len = row.chars.iter().map(|c| c.len_utf8()).sum::<usize>();
+            if offset <= len {
+                // an offset inside a multi-byte char points to that char
+                let x = row
+                    .chars
+                    .iter()
+                    .scan(0, |end, c| {
+                        *end += c.len_utf8();
+                        Some(*end)
+                    })
+                    .take_while(|end| *end <= offset)
+                    .count();
+                return (x, y);
+            }
+            // the newline
+            offset -= len + 1;
+        }
+        // past the end of the file, gets clamped to the last row
+        (usize::MAX, usize::MAX)
+    }
+
+    fn resize(&mut self, screenrows: usize, screencols: usize) {
+        (self.screenrows, self.screencols) = (screenrows, screencols);
+        // the cursor may be off the smaller screen
+        self.scroll();
     }
 
     fn scroll(&mut self) {
         let (cx, cy) = (self.cx, self.cy);
-        let (rows, cols) = (self.screenrows, self.screencols);
+        let (rows, cols) = (
+            self.screenrows,
+            self.screencols.saturating_sub(self.gutter_width()).max(1),
+        );
 
-        self.rx = if cy < self.rows.len() {
-            self.rows[cy].cx_to_rx(cx)
+        let (rx, width) = if cy < self.rows.len() {
+            let row = &self.rows[cy];
+            (row.cx_to_rx(cx), row.width_at(cx).max(1))
         } else {
-            0
+            (0, 1)
         };
-
-        let rx = self.rx;
+        self.rx = rx;
 
         if cy < self.row_offset {
             self.row_offset = cy;
@@ -600,8 +1761,132 @@ impl Tab {
         if rx < self.col_offset {
             self.col_offset = rx
         }
-        if rx >= self.col_offset + cols {
-            self.col_offset = rx - cols + 1
+        // make sure that wide characters under the cursor are fully visible
+        if rx + width > self.col_offset + cols {
+            self.col_offset = (rx + width).saturating_sub(cols)
+        }
+    }
+
+    /// Detects the filetype and applies the options and highlighting for it
+    fn detect_filetype(&mut self, config: &Config) {
+        let first_line = self
+            .rows
+            .first()
+            .map(|row| row.chars.iter().collect::<String>());
+        self.filetype = self
+            .filename
+            .as_deref()
+            .and_then(|fname| filetype::detect(fname, first_line.as_deref()));
+        self.options = config.options_for(self.filetype);
+        for row in &mut self.rows {
+            row.update(self.options.tab_stop);
+            row.hl.clear();
+        }
+        self.update_syntax(0);
+    }
+
+    /// Returns the no. of screen columns used for showing line numbers
+    fn gutter_width(&self) -> usize {
+        if self.options.line_numbers {
+            // digits in the largest line no. + a space after it
+            self.rows.len().max(1).to_string().len().max(3) + 1
+        } else {
+            0
+        }
+    }
+
+    /// Highlights rows starting from `at` until the state carried over
+    /// from one row to the next stops changing.
+    fn update_syntax(&mut self, at: usize) {
+        let Some(syntax) = self.filetype.and_then(|ft| ft.syntax) else {
+            return;
+        };
+        let mut state = match at {
+            0 => HlState::Normal,
+            at => self.rows[at - 1].hl_end,
+        };
+        for row in self.rows.iter_mut().skip(at) {
+            if row.hl_start == Some(state) {
+                break;
+            }
+            row.highlight(syntax, state);
+            state = row.hl_end;
+        }
+    }
+
+    /// Applies an edit to the rows without recording it in the undo history.
+    fn apply(&mut self, edit: &Edit) {
+        match edit {
+            Edit::Insert { x, y, chars } => {
+                let row = &mut self.rows[*y];
+                row.chars.splice(*x..*x, chars.iter().copied());
+                row.update(self.options.tab_stop);
+            }
+            Edit::Remove { x, y, chars } => {
+                let row = &mut self.rows[*y];
+                row.chars.drain(*x..*x + chars.len());
+                row.update(self.options.tab_stop);
+            }
+            Edit::Split { x, y } => {
+                let tail = self.rows[*y].chars.split_off(*x);
+                self.rows[*y].update(self.options.tab_stop);
+                self.rows
+                    .insert(*y + 1, Line::new(tail, self.options.tab_stop));
+            }
+            Edit::Join { y, .. } => {
+                let mut next = self.rows.remove(*y + 1);
+                self.rows[*y].chars.append(&mut next.chars);
+                self.rows[*y].update(self.options.tab_stop);
+            }
+            Edit::InsertRow { at, chars } => self
+                .rows
+                .insert(*at, Line::new(chars.clone(), self.options.tab_stop)),
+            Edit::RemoveRow { at, .. } => {
+                self.rows.remove(*at);
+            }
+        }
+
+        let (Edit::Insert { y, .. }
+        | Edit::Remove { y, .. }
+        | Edit::Split { y, .. }
+        | Edit::Join { y, .. }
+        | Edit::InsertRow { at: y, .. }
+        | Edit::RemoveRow { at: y, .. }) = edit;
+        self.update_syntax(*y);
+        self.dirty += 1;
+    }
+
+    /// Applies an edit, records it in the undo history and moves the cursor to `after`.
+    fn edit(&mut self, edit: Edit, after: (usize, usize)) {
+        let before = (self.cx, self.cy);
+        self.apply(&edit);
+        self.history.record(edit, before, after);
+        (self.cx, self.cy) = after;
+    }
+
+    fn undo(&mut self) {
+        self.clear_mark();
+        if let Some(change) = self.history.undo() {
+            for edit in change.edits.iter().rev() {
+                self.apply(&edit.inverse());
+            }
+            (self.cx, self.cy) = change.before;
+            if self.history.is_clean() {
+                self.dirty = 0;
+            }
+        }
+    }
+
+    fn redo(&mut self) {
+        self.clear_mark();
+        if let Some(change) = self.history.redo() {
+            for edit in &change.edits {
+                self.apply(edit);
+            }
+            (self.cx, self.cy) = change.after;
+            if self.history.is_clean() {
+                self.dirty = 0;
+            }
         }
     }
 
@@ -609,38 +1894,35 @@ impl Tab {
         if at > self.rows.len() {
             return;
         }
-        self.rows.insert(
-            at,
-            Line {
-                chars: row,
-                render: vec![],
-            },
-        );
-        self.rows[at].update();
-        self.dirty += 1;
+        self.edit(Edit::InsertRow { at, chars: row }, (self.cx, self.cy));
     }
 
     fn insert_char(&mut self, c: char) {
         if self.cy == self.rows.len() {
             self.insert_row(self.rows.len(), vec![]);
         }
-        self.rows[self.cy].chars.insert(self.cx, c);
-        self.rows[self.cy].update();
-        self.cx += 1;
-        self.dirty += 1;
+        let (cx, cy) = (self.cx, self.cy);
+        self.edit(
+            Edit::Insert {
+                x: cx,
+                y: cy,
+                chars: vec![c],
+            },
+            (cx + 1, cy),
+        );
     }
 
     fn insert_newline(&mut self) {
-        if self.cx == 0 {
-            self.insert_row(self.cy, vec![]);
+        let (cx, cy) = (self.cx, self.cy);
+        let edit = if cx == 0 || cy == self.rows.len() {
+            Edit::InsertRow {
+                at: cy,
+                chars: vec![],
+            }
         } else {
-            let row = self.rows[self.cy].clone();
-            self.insert_row(self.cy + 1, row.chars[self.cx..].to_vec());
-            self.rows[self.cy].chars = row.chars[..self.cx].to_vec();
-            self.rows[self.cy].update();
-        }
-        self.cy += 1;
-        self.cx = 0;
+            Edit::Split { x: cx, y: cy }
+        };
+        self.edit(edit, (0, cy + 1));
     }
 
     fn del_char(&mut self) {
@@ -648,65 +1930,411 @@ impl Tab {
             return;
         }
 
-        let row = &mut self.rows[self.cy];
-        if self.cx > 0 {
-            let pos = self.cx - 1;
-            if pos >= row.size() {
+        let (cx, cy) = (self.cx, self.cy);
+        if cx > 0 {
+            if cx > self.rows[cy].size() {
                 return;
             }
-            row.chars.remove(pos);
-            row.update();
-            self.cx -= 1
+            // remove the whole grapheme before the cursor
+            let pos = self.rows[cy].prev_cx(cx);
+            let chars = self.rows[cy].chars[pos..cx].to_vec();
+            self.edit(
+                Edit::Remove {
+                    x: pos,
+                    y: cy,
+                    chars,
+                },
+                (pos, cy),
+            );
         } else {
-            self.cx = self.rows[self.cy - 1].size();
-            let mut row = self.rows[self.cy].chars.clone();
-            self.rows[self.cy - 1].chars.append(&mut row);
-            self.rows[self.cy - 1].update();
-            self.rows.remove(self.cy);
-            self.cy -= 1;
+            let x = self.rows[cy - 1].size();
+            self.edit(Edit::Join { x, y: cy - 1 }, (x, cy - 1));
         }
-        self.dirty += 1;
     }
 
-    fn find_cb(&mut self, query: &str, key: EditorKey) {
-        match key {
-            EditorKey::Char('\r') | EditorKey::Char('\x1b') => {
-                self.last_match = -1;
-                self.direction = 1;
-                return;
+    /// Deletes the grapheme under the cursor, or joins the next row at the end of a row
+    fn del_char_forward(&mut self) {
+        let (cx, cy) = (self.cx, self.cy);
+        let Some(row) = self.rows.get(cy) else {
+            return;
+        };
+        if cx < row.size() {
+            let end = row.next_cx(cx);
+            let chars = row.chars[cx..end].to_vec();
+            self.edit(
+                Edit::Remove {
+                    x: cx,
+                    y: cy,
+                    chars,
+                },
+                (cx, cy),
+            );
+        } else if cy + 1 < self.rows.len() {
+            let x = row.size();
+            self.edit(Edit::Join { x, y: cy }, (x, cy));
+        }
+    }
+
+    /// Sets the mark at the cursor, or removes it if it's already set
+    fn toggle_mark(&mut self) -> bool {
+        self.shift_selection = false;
+        self.mark = match self.mark {
+            Some(_) => None,
+            None => Some((self.cx, self.cy)),
+        };
+        self.mark.is_some()
+    }
+
+    fn clear_mark(&mut self) {
+        self.mark = None;
+        self.shift_selection = false;
+    }
+
+    /// Moves (x, y) onto the text, eg. after the rows it was on got removed
+    fn clamp_pos(&self, (x, y): (usize, usize)) -> (usize, usize) {
+        match self.rows.get(y) {
+            Some(row) => (row.snap_cx(min(x, row.size())), y),
+            None => self
+                .rows
+                .last()
+                .map_or((0, 0), |row| (row.size(), self.rows.len() - 1)),
+        }
+    }
+
+    /// Returns the (x, y) positions of the start and the end (exclusive) of the selection
+    fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        let mark = self.clamp_pos(self.mark?);
+        let cursor = self.clamp_pos((self.cx, self.cy));
+        // positions are ordered by row first
+        let (start, end) = if (mark.1, mark.0) <= (cursor.1, cursor.0) {
+            (mark, cursor)
+        } else {
+            (cursor, mark)
+        };
+        (start != end).then_some((start, end))
+    }
+
+    /// Removes the selected text, returns false if nothing is selected.
+    /// The mark is cleared either way.
+    fn delete_selection(&mut self) -> bool {
+        let selection = self.selection();
+        self.clear_mark();
+        let Some(((sx, sy), (ex, ey))) = selection else {
+            return false;
+        };
+
+        self.history.begin_group();
+        if sy == ey {
+            let chars = self.rows[sy].chars[sx..ex].to_vec();
+            self.edit(
+                Edit::Remove {
+                    x: sx,
+                    y: sy,
+                    chars,
+                },
+                (sx, sy),
+            );
+        } else {
+            let tail = self.rows[sy].chars[sx..].to_vec();
+            if !tail.is_empty() {
+                self.edit(
+                    Edit::Remove {
+                        x: sx,
+                        y: sy,
+                        chars: tail,
+                    },
+                    (sx, sy),
+                );
             }
-            EditorKey::ArrowRight | EditorKey::ArrowDown => self.direction = 1,
-            EditorKey::ArrowLeft | EditorKey::ArrowUp => self.direction = -1,
-            _ => {
-                self.last_match = -1;
-                self.direction = 1;
+            for _ in sy + 1..ey {
+                let chars = self.rows[sy + 1].chars.clone();
+                self.edit(Edit::RemoveRow { at: sy + 1, chars }, (sx, sy));
+            }
+            let head = self.rows[sy + 1].chars[..ex].to_vec();
+            if !head.is_empty() {
+                self.edit(
+                    Edit::Remove {
+                        x: 0,
+                        y: sy + 1,
+                        chars: head,
+                    },
+                    (sx, sy),
+                );
             }
+            self.edit(Edit::Join { x: sx, y: sy }, (sx, sy));
+        }
+        self.history.end_group();
+        true
+    }
+
+    /// Runs an edit in place of the selection (if any), as a single undo step
+    fn replace_selection(&mut self, edit: impl FnOnce(&mut Self)) {
+        if self.selection().is_none() {
+            // the mark of an empty selection would turn the edit into a selection
+            self.clear_mark();
+            edit(self);
+            return;
+        }
+        self.history.begin_group();
+        self.delete_selection();
+        edit(self);
+        self.history.end_group();
+    }
+
+    /// Indents (or dedents) the lines of the selection, or the current line if nothing is selected.
+    /// The lines of the selection are fully selected afterwards.
+    fn indent_selection(&mut self, dedent: bool) {
+        let selected = self.selection();
+        let (first, last) = match selected {
+            // a selection ending at the start of a line doesn't include it
+            Some(((_, sy), (0, ey))) if ey > sy => (sy, ey - 1),
+            Some(((_, sy), (_, ey))) => (sy, ey),
+            None if self.cy < self.rows.len() => (self.cy, self.cy),
+            None => return,
+        };
+        let indent = if self.options.expand_tabs {
+            vec![' '; self.options.tab_stop]
+        } else {
+            vec!['\t']
         };
 
-        if self.last_match == -1 {
-            self.direction = 1;
+        let (cx, len) = (self.cx, self.rows[first].size());
+        self.history.begin_group();
+        for y in first..=last {
+            let chars = &self.rows[y].chars;
+            if dedent {
+                let n = match chars.first() {
+                    Some('\t') => 1,
+                    _ => chars
+                        .iter()
+                        .take(self.options.tab_stop)
+                        .take_while(|c| **c == ' ')
+                        .count(),
+                };
+                let chars = chars[..n].to_vec();
+                if !chars.is_empty() {
+                    self.edit(Edit::Remove { x: 0, y, chars }, (0, y));
+                }
+            } else if !chars.is_empty() {
+                let chars = indent.clone();
+                self.edit(Edit::Insert { x: 0, y, chars }, (0, y));
+            }
         }
+        self.history.end_group();
 
-        let mut current = self.last_match;
+        if selected.is_some() {
+            self.mark = Some((0, first));
+            self.shift_selection = false;
+            (self.cx, self.cy) = (self.rows[last].size(), last);
+        } else {
+            // keep the cursor on the same char
+            (self.cx, self.cy) = ((cx + self.rows[first].size()).saturating_sub(len), first);
+        }
+    }
 
-        for _ in 0..self.rows.len() {
-            current += self.direction;
-            if current == -1 {
-                current = self.rows.len() as i8 - 1;
-            } else if current == self.rows.len() as i8 {
-                current = 0;
+    /// Returns the text between two (x, y) positions, with rows separated by newlines
+    fn text_between(&self, (sx, sy): (usize, usize), (ex, ey): (usize, usize)) -> String {
+        if sy == ey {
+            return self.rows[sy].chars[sx..ex].iter().collect();
+        }
+        let mut text = self.rows[sy].chars[sx..].iter().collect::<String>();
+        for row in &self.rows[sy + 1..ey] {
+            text.push('\n');
+            text.extend(&row.chars);
+        }
+        text.push('\n');
+        text.extend(&self.rows[ey].chars[..ex]);
+        text
+    }
+
+    /// Returns the selected text, or the current line if nothing is selected,
+    /// and removes it if `cut` is set.
+    fn kill(&mut self, cut: bool) -> Option<String> {
+        if let Some((start, end)) = self.selection() {
+            let text = self.text_between(start, end);
+            if cut {
+                self.delete_selection();
             }
+            return Some(text);
+        }
 
-            let row = &self.rows[current as usize];
-            let s = row.render.iter().collect::<String>();
-            if let Some(xidx) = s.find(query) {
-                self.last_match = current;
-                self.cy = current as usize;
-                self.cx = row.rx_to_cx(xidx);
-                self.row_offset = self.rows.len();
-                break;
+        let row = self.rows.get(self.cy)?;
+        let text = row.chars.iter().chain(['\n'].iter()).collect();
+        if cut {
+            let (at, chars) = (self.cy, row.chars.clone());
+            self.edit(Edit::RemoveRow { at, chars }, (0, at));
+        }
+        Some(text)
+    }
+
+    /// Inserts text at the cursor (in place of the selection), leaving the cursor after it.
+    ///
+    /// Each line of the text is inserted with a single edit.
+    fn paste(&mut self, text: &str) {
+        let mut lines = text
+            .split('\n')
+            .map(|line| line.chars().collect::<Vec<_>>());
+        let first = lines.next().unwrap_or_default();
+        let rest = lines.collect::<Vec<_>>();
+
+        self.history.begin_group();
+        self.delete_selection();
+        if self.cy == self.rows.len() {
+            self.insert_row(self.rows.len(), vec![]);
+        }
+        let start = (self.cx, self.cy);
+        let (mut x, mut y) = start;
+
+        if !first.is_empty() {
+            let n = first.len();
+            self.edit(Edit::Insert { x, y, chars: first }, (x + n, y));
+            x += n;
+        }
+        if let Some((last, middle)) = rest.split_last() {
+            self.edit(Edit::Split { x, y }, (0, y + 1));
+            for line in middle {
+                y += 1;
+                self.insert_row(y, line.clone());
+            }
+            y += 1;
+            x = last.len();
+            if !last.is_empty() {
+                self.edit(
+                    Edit::Insert {
+                        x: 0,
+                        y,
+                        chars: last.clone(),
+                    },
+                    (x, y),
+                );
+            }
+        }
+        self.history.end_group();
+
+        (self.cx, self.cy) = (x, y);
+        self.last_paste = Some((start, (x, y)));
+    }
+
+    /// Returns the first match at or after (x, y) and before `end` (x, y)
+    /// as (row, byte range within the row, contents of the row).
+    fn find_from(
+        &self,
+        matcher: &Matcher,
+        x: usize,
+        y: usize,
+        end: (usize, usize),
+    ) -> Option<(usize, Range<usize>, String)> {
+        let (end_x, end_y) = end;
+        for (i, row) in self.rows.iter().enumerate().take(end_y + 1).skip(y) {
+            let x = if i == y { x } else { 0 };
+            if x > row.size() {
+                continue;
+            }
+
+            let s = row.chars.iter().collect::<String>();
+            let start = s.char_indices().nth(x).map_or(s.len(), |(i, _)| i);
+            if let Some(m) = matcher.find_at(&s, start) {
+                if i == end_y && s[..m.end].chars().count() > end_x {
+                    return None;
+                }
+                return Some((i, m, s));
+            }
+        }
+        None
+    }
+
+    /// Replaces the chars in `range` of row `y`
+    fn replace_text(&mut self, y: usize, range: Range<usize>, chars: Vec<char>) {
+        let removed = self.rows[y].chars[range.clone()].to_vec();
+        if !removed.is_empty() {
+            self.edit(
+                Edit::Remove {
+                    x: range.start,
+                    y,
+                    chars: removed,
+                },
+                (range.start, y),
+            );
+        }
+        if !chars.is_empty() {
+            let end = range.start + chars.len();
+            self.edit(
+                Edit::Insert {
+                    x: range.start,
+                    y,
+                    chars,
+                },
+                (end, y),
+            );
+        }
+    }
+
+    /// Returns the char ranges of the matches in row `y`
+    fn row_matches(&self, matcher: &Matcher, y: usize) -> Vec<Range<usize>> {
+        let s = self.rows[y].chars.iter().collect::<String>();
+        matcher
+            .find_iter(&s)
+            .map(|m| {
+                // convert the byte offsets into char indexes
+                let start = s[..m.start].chars().count();
+                start..start + s[m].chars().count()
+            })
+            .collect()
+    }
+
+    /// Returns the start of every match in the buffer as (cy, cx), in order
+    fn all_matches(&self, matcher: &Matcher) -> Vec<(usize, usize)> {
+        (0..self.rows.len())
+            .flat_map(|y| {
+                self.row_matches(matcher, y)
+                    .into_iter()
+                    .map(move |m| (y, m.start))
+            })
+            .collect()
+    }
+
+    /// Returns (index of the match at the cursor, total no. of matches)
+    fn match_count(&self, matcher: &Matcher) -> (Option<usize>, usize) {
+        let matches = self.all_matches(matcher);
+        let current = matches.iter().position(|&m| m == (self.cy, self.cx));
+        (current, matches.len())
+    }
+
+    fn find_cb(&mut self, matcher: &Matcher, key: EditorKey) {
+        match key {
+            EditorKey::Char('\r') | EditorKey::Char('\x1b') => {
+                self.last_match = None;
+                self.forward = true;
+                return;
+            }
+            EditorKey::Char(CTRL_N) => self.forward = true,
+            EditorKey::Char(CTRL_P) => self.forward = false,
+            _ => {
+                self.last_match = None;
+                self.forward = true;
             }
+        };
+
+        let matches = self.all_matches(matcher);
+        if matches.is_empty() {
+            return;
         }
+
+        // a new query starts from the top, otherwise step from the last match and wrap around
+        let i = match self.last_match {
+            None => 0,
+            Some((x, y)) if self.forward => matches.iter().position(|&m| m > (y, x)).unwrap_or(0),
+            Some((x, y)) => matches
+                .iter()
+                .rposition(|&m| m < (y, x))
+                .unwrap_or(matches.len() - 1),
+        };
+
+        let (cy, cx) = matches[i];
+        self.last_match = Some((cx, cy));
+        self.cy = cy;
+        self.cx = cx;
+        self.row_offset = self.rows.len();
     }
 }
 
@@ -716,7 +2344,14 @@ fn main() {
     if let Err(e) = enable_raw_mode() {
         die("Failed to enable raw mode", e);
     };
-    let mut editor = Editor::new();
+    if let Err(e) = watch_resize() {
+        die("Failed to watch for window resizes", e);
+    };
+    if let Err(e) = enable_kitty_keyboard() {
+        die("Failed to query the keyboard protocol", e);
+    };
+    let (config, errors) = Config::load();
+    let mut editor = Editor::new(config);
     if let Err(e) = editor.init() {
         die("Failed to get window size", e)
     };
@@ -724,20 +2359,82 @@ fn main() {
     if args.len() >= 2 {
         for path in &args[1..] {
             editor.create_tab();
-            editor
+            let result = editor
                 .tab
                 .as_ref()
                 .unwrap()
                 .borrow_mut()
-                .load_file(Path::new(path));
+                .load_file(Path::new(path), &editor.config);
+            if let Err(e) = result {
+                die("Could not open file", e);
+            }
         }
         editor.set_active_tab(0);
     }
 
-    editor.set_status_message("HELP: Ctrl-S = save | Ctrl-Q = quit | Ctrl-F = find");
+    match errors.first() {
+        Some(e) if errors.len() > 1 => editor.set_status_message(&format!(
+            "Config error: {} (and {} more)",
+            e,
+            errors.len() - 1
+        )),
+        Some(e) => editor.set_status_message(&format!("Config error: {}", e)),
+        None => {
+            let keymap = &editor.config.keymap;
+            let help = [
+                (Command::Save, "save"),
+                (Command::Quit, "quit"),
+                (Command::Find, "find"),
+                (Command::Replace, "replace"),
+                (Command::Undo, "undo"),
+                (Command::Redo, "redo"),
+            ]
+            .iter()
+            .filter_map(|(cmd, name)| keymap.keys_for(*cmd).map(|k| format!("{} = {}", k, name)))
+            .collect::<Vec<_>>()
+            .join(" | ");
+            editor.set_status_message(&format!("HELP: {}", help));
+        }
+    }
 
     loop {
         editor.refresh_screen().unwrap();
         editor.process_keypress();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tab_with(text: &str) -> Tab {
+        let mut tab = Tab::new(10, 80, Config::new().options);
+        tab.paste(text);
+        (tab.cx, tab.cy) = (0, 0);
+        tab
+    }
+
+    fn text(tab: &Tab) -> String {
+        tab.text_between((0, 0), (tab.rows[tab.cy].size(), tab.cy))
+    }
+
+    #[test]
+    fn typing_with_an_empty_selection() {
+        let mut tab = tab_with("hello");
+        tab.toggle_mark();
+        tab.process_buffer_keypress(EditorKey::Char('a'));
+        tab.process_buffer_keypress(EditorKey::Char('b'));
+        assert_eq!(text(&tab), "abhello");
+        assert_eq!(tab.mark, None);
+    }
+
+    #[test]
+    fn pasting_with_an_empty_selection() {
+        let mut tab = tab_with("hello");
+        tab.toggle_mark();
+        tab.paste("ab");
+        tab.paste("c");
+        assert_eq!(text(&tab), "abchello");
+        assert_eq!(tab.mark, None);
+    }
+}
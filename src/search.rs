@@ -0,0 +1,141 @@
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum CaseMode {
+    #[default]
+    Sensitive,
+    Insensitive,
+    /// Case-insensitive unless the query contains an uppercase letter
+    Smart,
+}
+
+/// Options which can be toggled from the search prompt
+#[derive(Clone, Copy, Default)]
+pub struct SearchOptions {
+    pub regex: bool,
+    pub case: CaseMode,
+    pub whole_word: bool,
+}
+
+impl SearchOptions {
+    /// Switches to the next case mode: sensitive -> insensitive -> smart
+    pub fn cycle_case(&mut self) {
+        self.case = match self.case {
+            CaseMode::Sensitive => CaseMode::Insensitive,
+            CaseMode::Insensitive => CaseMode::Smart,
+            CaseMode::Smart => CaseMode::Sensitive,
+        };
+    }
+
+    /// Returns the active options in the form shown in the prompt, eg. `[regex, smart case]`
+    pub fn label(&self) -> String {
+        let mut flags = Vec::new();
+        if self.regex {
+            flags.push("regex");
+        }
+        match self.case {
+            CaseMode::Sensitive => {}
+            CaseMode::Insensitive => flags.push("ignore case"),
+            CaseMode::Smart => flags.push("smart case"),
+        }
+        if self.whole_word {
+            flags.push("whole word");
+        }
+        if flags.is_empty() {
+            String::new()
+        } else {
+            format!("[{}]", flags.join(", "))
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[derive(Clone)]
+pub struct Matcher {
+    re: Regex,
+    whole_word: bool,
+    /// Whether `$1`, `${name}` etc. in replacements refer to capture groups
+    expand: bool,
+}
+
+impl Matcher {
+    pub fn new(query: &str, options: SearchOptions) -> Result<Self, String> {
+        // plain text queries are matched literally
+        let pattern = if options.regex {
+            query.to_owned()
+        } else {
+            regex::escape(query)
+        };
+        let ignore_case = match options.case {
+            CaseMode::Sensitive => false,
+            CaseMode::Insensitive => true,
+            CaseMode::Smart => !query.chars().any(char::is_uppercase),
+        };
+
+        let re = RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|e| match e {
+                // syntax errors span multiple lines (pattern, pointer to the error, message),
+                // only the message fits in the message bar
+                regex::Error::Syntax(msg) => {
+                    let msg = msg.lines().last().unwrap_or_default();
+                    msg.trim_start_matches("error: ").to_owned()
+                }
+                e => e.to_string(),
+            })?;
+
+        Ok(Self {
+            re,
+            whole_word: options.whole_word,
+            expand: options.regex,
+        })
+    }
+
+    /// Returns the byte ranges of all the (non-overlapping) matches in `s`
+    pub fn find_iter<'a>(&'a self, s: &'a str) -> impl Iterator<Item = Range<usize>> + 'a {
+        self.re
+            .find_iter(s)
+            .map(|m| m.range())
+            .filter(move |m| !self.whole_word || Self::is_whole_word(s, m))
+    }
+
+    /// Returns the byte range of the first match in `s` which starts at or after `start`
+    pub fn find_at(&self, s: &str, mut start: usize) -> Option<Range<usize>> {
+        while start <= s.len() {
+            let m = self.re.find_at(s, start)?.range();
+            if !self.whole_word || Self::is_whole_word(s, &m) {
+                return Some(m);
+            }
+            // retry from the next char
+            start = m.start + s[m.start..].chars().next().map_or(1, char::len_utf8);
+        }
+        None
+    }
+
+    /// Returns the text which replaces the match `m` in `s`.
+    ///
+    /// In regex mode, capture groups referenced in the template (`$1`, `${name}`)
+    /// are expanded, otherwise the template is used as is.
+    pub fn expand(&self, s: &str, m: &Range<usize>, template: &str) -> String {
+        if !self.expand {
+            return template.to_owned();
+        }
+        let mut dst = String::new();
+        if let Some(caps) = self.re.captures_at(s, m.start) {
+            caps.expand(template, &mut dst);
+        }
+        dst
+    }
+
+    fn is_whole_word(s: &str, m: &Range<usize>) -> bool {
+        let before = s[..m.start].chars().next_back();
+        let after = s[m.end..].chars().next();
+        !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+    }
+}
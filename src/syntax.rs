@@ -0,0 +1,135 @@
+/// Highlight numeric literals
+pub const HL_NUMBERS: u8 = 1 << 0;
+/// Highlight lines starting with `#` as headings (Markdown)
+pub const HL_HEADINGS: u8 = 1 << 1;
+/// Highlight lines starting with `[` as section headers (TOML, INI)
+pub const HL_SECTIONS: u8 = 1 << 2;
+/// Highlight char literals such as `'x'` as strings, a lone `'` is left alone (Rust lifetimes)
+pub const HL_CHARS: u8 = 1 << 3;
+
+/// Rules used for highlighting a language
+pub struct Syntax {
+    /// Highlighted as `Highlight::Keyword1`
+    pub keywords: &'static [&'static str],
+    /// Highlighted as `Highlight::Keyword2`
+    pub types: &'static [&'static str],
+    pub line_comment: Option<&'static str>,
+    pub block_comment: Option<(&'static str, &'static str)>,
+    /// Delimiters of strings which end along with the line
+    pub strings: &'static [&'static str],
+    /// Delimiters of strings which can span multiple lines
+    pub multiline_strings: &'static [&'static str],
+    /// Delimiters of strings in which `\` doesn't escape the next char
+    pub raw_strings: &'static [&'static str],
+    pub flags: u8,
+}
+
+pub const RUST: Syntax = Syntax {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+        "true", "type", "unsafe", "use", "where", "while",
+    ],
+    types: &[
+        "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32",
+        "i64", "i128", "isize", "f32", "f64", "String", "Vec", "Option", "Result", "Box", "Some",
+        "None", "Ok", "Err",
+    ],
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    strings: &[],
+    multiline_strings: &["\""],
+    raw_strings: &[],
+    flags: HL_NUMBERS | HL_CHARS,
+};
+
+pub const C: Syntax = Syntax {
+    keywords: &[
+        "switch", "if", "while", "for", "break", "continue", "return", "else", "struct", "union",
+        "typedef", "static", "enum", "class", "case", "default", "do", "goto", "sizeof", "const",
+        "extern", "volatile", "#include", "#define", "#ifdef", "#ifndef", "#endif", "#if", "#else",
+    ],
+    types: &[
+        "int", "long", "double", "float", "char", "unsigned", "signed", "void", "short", "bool",
+        "size_t",
+    ],
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    strings: &["\"", "'"],
+    multiline_strings: &[],
+    raw_strings: &[],
+    flags: HL_NUMBERS,
+};
+
+pub const PYTHON: Syntax = Syntax {
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+        "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is",
+        "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with",
+        "yield",
+    ],
+    types: &[
+        "True", "False", "None", "int", "float", "str", "bool", "list", "dict", "set", "tuple",
+        "bytes", "self",
+    ],
+    line_comment: Some("#"),
+    block_comment: None,
+    strings: &["\"", "'"],
+    multiline_strings: &["\"\"\"", "'''"],
+    raw_strings: &[],
+    flags: HL_NUMBERS,
+};
+
+pub const MARKDOWN: Syntax = Syntax {
+    keywords: &[],
+    types: &[],
+    line_comment: None,
+    block_comment: Some(("<!--", "-->")),
+    strings: &["`"],
+    multiline_strings: &["```"],
+    raw_strings: &["`", "```"],
+    flags: HL_HEADINGS,
+};
+
+pub const TOML: Syntax = Syntax {
+    keywords: &["true", "false"],
+    types: &[],
+    line_comment: Some("#"),
+    block_comment: None,
+    strings: &["\"", "'"],
+    multiline_strings: &["\"\"\"", "'''"],
+    raw_strings: &["'", "'''"],
+    flags: HL_NUMBERS | HL_SECTIONS,
+};
+
+pub const SHELL: Syntax = Syntax {
+    keywords: &[
+        "if", "then", "else", "elif", "fi", "case", "esac", "for", "select", "while", "until",
+        "do", "done", "in", "function", "time", "return", "exit", "break", "continue", "local",
+        "export", "readonly", "declare", "unset", "shift", "source",
+    ],
+    types: &[
+        "echo", "printf", "cd", "test", "read", "set", "eval", "exec", "trap",
+    ],
+    line_comment: Some("#"),
+    block_comment: None,
+    strings: &[],
+    multiline_strings: &["\"", "'"],
+    raw_strings: &["'"],
+    flags: HL_NUMBERS,
+};
+
+pub const MAKE: Syntax = Syntax {
+    keywords: &[
+        "ifeq", "ifneq", "ifdef", "ifndef", "else", "endif", "include", "define", "endef",
+        "export", "override",
+    ],
+    types: &[],
+    line_comment: Some("#"),
+    block_comment: None,
+    strings: &[],
+    multiline_strings: &[],
+    raw_strings: &[],
+    flags: 0,
+};
@@ -1,18 +1,44 @@
 use std::{
     fmt::Display,
-    io::{self, BufRead, Write},
+    io::{self, Write},
     mem,
     process::exit,
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+    time::Duration,
 };
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use libc::__errno_location as errno_location;
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+use libc::__error as errno_location;
 use libc::{
-    atexit, ioctl, tcgetattr, tcsetattr, termios, winsize, BRKINT, CS8, ECHO, ICANON, ICRNL,
-    IEXTEN, INPCK, ISIG, ISTRIP, IXON, OPOST, STDIN_FILENO, STDOUT_FILENO, TCSAFLUSH, TIOCGWINSZ,
-    VMIN, VTIME,
+    atexit, c_int, fcntl, ioctl, nfds_t, pipe, poll, pollfd, read, sighandler_t, signal, tcgetattr,
+    tcsetattr, termios, winsize, BRKINT, CS8, ECHO, FD_CLOEXEC, F_SETFD, F_SETFL, ICANON, ICRNL,
+    IEXTEN, INPCK, ISIG, ISTRIP, IXON, OPOST, O_NONBLOCK, POLLIN, SIGWINCH, SIG_ERR, STDIN_FILENO,
+    STDOUT_FILENO, TCSAFLUSH, TIOCGWINSZ, VMIN, VTIME,
 };
 
+use crate::key::{EditorKey, Modifiers};
+
 /// Stores initial terminal config
 static mut ORIG_TERMIOS: termios = unsafe { mem::zeroed() };
+/// Read and write ends of the pipe through which the signal handler wakes up the event loop
+static SIGNAL_PIPE: [AtomicI32; 2] = [AtomicI32::new(-1), AtomicI32::new(-1)];
+/// Byte given back with `unread_byte`, -1 if there's none
+static UNREAD_BYTE: AtomicI32 = AtomicI32::new(-1);
+/// Whether keys are reported using the kitty keyboard protocol
+static KITTY_KEYBOARD: AtomicBool = AtomicBool::new(false);
+/// Max. time to wait for the terminal to reply to a query
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Reason for `wait` returning
+pub enum Wakeup {
+    /// Stdin has input to read
+    Input,
+    /// The terminal window was resized
+    Resize,
+    Timeout,
+}
 
 pub fn die<E: Display>(message: &str, error: E) -> ! {
     clear_screen();
@@ -24,7 +50,7 @@ pub fn die<E: Display>(message: &str, error: E) -> ! {
 pub fn enable_raw_mode() -> io::Result<()> {
     // Ref: https://www.man7.org/linux/man-pages/man3/termios.3.html
     unsafe {
-        if tcgetattr(STDIN_FILENO, &mut ORIG_TERMIOS) != 0 {
+        if tcgetattr(STDIN_FILENO, &raw mut ORIG_TERMIOS) != 0 {
             return Err(io::Error::last_os_error());
         };
         if atexit(disable_raw_mode) != 0 {
@@ -56,8 +82,9 @@ pub fn enable_raw_mode() -> io::Result<()> {
         // Control Characters:
         // VMIN - sets min. no. of bytes of input needed before read can return
         // VTIME - sets max. amount of time to wait to before read returns
-        raw.c_cc[VMIN] = 0;
-        raw.c_cc[VTIME] = 1;
+        // Reads block, stdin is polled for input (with a timeout) before reading.
+        raw.c_cc[VMIN] = 1;
+        raw.c_cc[VTIME] = 0;
 
         // TCSAFLUSH - change occurs after all output has been transmitted &
         // all input that has been received but not read will be discarded before the change is made
@@ -65,7 +92,14 @@ pub fn enable_raw_mode() -> io::Result<()> {
             return Err(io::Error::last_os_error());
         };
     }
-    Ok(())
+
+    // ?2004h - Enable bracketed paste mode,
+    // pasted text is wrapped in <esc>[200~ and <esc>[201~
+    write(b"\x1b[?2004h")?;
+    // ?1002h - Report mouse button presses, releases and movement while a button is held
+    // ?1006h - Use the SGR format for mouse reports, eg. <esc>[<0;12;5M
+    write(b"\x1b[?1002h\x1b[?1006h")?;
+    io::stdout().flush()
 }
 
 pub fn clear_screen() {
@@ -81,6 +115,163 @@ pub fn clear_screen() {
     io::stdout().flush().unwrap();
 }
 
+/// Sets the system clipboard through the terminal.
+/// This works over SSH too, but some terminals ignore it or need it to be enabled.
+pub fn set_clipboard(text: &str) -> io::Result<()> {
+    // OSC 52 - Manipulate Selection Data
+    // arg c - the clipboard, followed by the base64 encoded text
+    write(format!("\x1b]52;c;{}\x07", base64(text.as_bytes())).as_bytes())?;
+    io::stdout().flush()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        // each 3 bytes are split into 4 groups of 6 bits, missing bytes are padded with `=`
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+extern "C" fn handle_sigwinch(_: c_int) {
+    // only async-signal-safe functions can be called here,
+    // the pipe is non-blocking so a full pipe doesn't hang the handler
+    let fd = SIGNAL_PIPE[1].load(Ordering::Relaxed);
+    // errno is restored since the handler can run between a failing call
+    // (eg. poll being interrupted) and the check of its error
+    unsafe {
+        let errno = *errno_location();
+        libc::write(fd, [0u8].as_ptr().cast(), 1);
+        *errno_location() = errno;
+    }
+}
+
+/// Installs a handler for SIGWINCH, which is sent when the terminal window is resized.
+/// The handler wakes up `wait` through a pipe (the self-pipe trick).
+pub fn watch_resize() -> io::Result<()> {
+    unsafe {
+        let mut fds = [0; 2];
+        if pipe(fds.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for (fd, end) in fds.iter().zip(&SIGNAL_PIPE) {
+            // the pipe shouldn't be inherited by the clipboard commands
+            if fcntl(*fd, F_SETFL, O_NONBLOCK) == -1 || fcntl(*fd, F_SETFD, FD_CLOEXEC) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            end.store(*fd, Ordering::Relaxed);
+        }
+
+        if signal(
+            SIGWINCH,
+            handle_sigwinch as extern "C" fn(c_int) as sighandler_t,
+        ) == SIG_ERR
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Waits until stdin has input, the terminal is resized or the timeout (if any) expires
+pub fn wait(timeout: Option<Duration>) -> io::Result<Wakeup> {
+    if UNREAD_BYTE.load(Ordering::Relaxed) != -1 {
+        return Ok(Wakeup::Input);
+    }
+    let signal_fd = SIGNAL_PIPE[0].load(Ordering::Relaxed);
+    // negative fds (the pipe not being set up) are ignored by poll
+    let mut fds = [STDIN_FILENO, signal_fd].map(|fd| pollfd {
+        fd,
+        events: POLLIN,
+        revents: 0,
+    });
+    poll_fds(&mut fds, timeout)?;
+
+    if fds[1].revents != 0 {
+        // drain the pipe as several signals may have arrived
+        let mut buf = [0u8; 64];
+        while unsafe { read(signal_fd, buf.as_mut_ptr().cast(), buf.len()) } > 0 {}
+        return Ok(Wakeup::Resize);
+    }
+    if fds[0].revents != 0 {
+        return Ok(Wakeup::Input);
+    }
+    Ok(Wakeup::Timeout)
+}
+
+/// Waits until stdin has input or the timeout expires, returns whether there's input.
+///
+/// Unlike `wait` a resize doesn't interrupt the wait, it's reported by the next call to `wait`.
+pub fn wait_for_input(timeout: Duration) -> io::Result<bool> {
+    if UNREAD_BYTE.load(Ordering::Relaxed) != -1 {
+        return Ok(true);
+    }
+    let mut fds = [pollfd {
+        fd: STDIN_FILENO,
+        events: POLLIN,
+        revents: 0,
+    }];
+    poll_fds(&mut fds, Some(timeout))?;
+    Ok(fds[0].revents != 0)
+}
+
+/// Reads a byte from stdin.
+///
+/// `io::stdin()` isn't used since its buffer would hide pending input from `poll`.
+pub fn read_byte() -> io::Result<u8> {
+    if let Ok(b) = u8::try_from(UNREAD_BYTE.swap(-1, Ordering::Relaxed)) {
+        return Ok(b);
+    }
+    let mut buf = [0u8; 1];
+    loop {
+        match unsafe { read(STDIN_FILENO, buf.as_mut_ptr().cast(), 1) } {
+            1 => return Ok(buf[0]),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// Gives back a byte so that the next `read_byte` returns it again,
+/// used when a byte turns out not to be part of an escape sequence.
+pub fn unread_byte(b: u8) {
+    UNREAD_BYTE.store(b.into(), Ordering::Relaxed);
+}
+
+fn poll_fds(fds: &mut [pollfd], timeout: Option<Duration>) -> io::Result<()> {
+    // round up to whole milliseconds so that a timer doesn't fire early, -1 waits forever
+    let timeout = timeout.map_or(-1, |t| {
+        c_int::try_from(t.as_nanos().div_ceil(1_000_000)).unwrap_or(c_int::MAX)
+    });
+    loop {
+        if unsafe { poll(fds.as_mut_ptr(), fds.len() as nfds_t, timeout) } != -1 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        // signals interrupt poll, the signal pipe tells whether one needs handling
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
 pub fn get_window_size() -> io::Result<(usize, usize)> {
     unsafe {
         let mut ws: winsize = mem::zeroed();
@@ -106,7 +297,15 @@ fn get_cursor_position() -> io::Result<(usize, usize)> {
 
     let mut buf = Vec::new();
     // Cursor Position Report: "<Esc>[rows;colsR"
-    io::stdin().lock().read_until(b'R', &mut buf)?;
+    while !buf.ends_with(b"R") {
+        if !wait_for_input(QUERY_TIMEOUT)? {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no reply to the cursor position query",
+            ));
+        }
+        buf.push(read_byte()?);
+    }
 
     match String::from_utf8(buf) {
         Ok(v) => {
@@ -128,9 +327,88 @@ fn write(buf: &[u8]) -> io::Result<()> {
     io::stdout().lock().write_all(buf)
 }
 
+/// Parses a key event of the kitty keyboard protocol, `<esc>[code;modifiers u`
+/// where the code is the key's unicode codepoint.
+///
+/// Ref: https://sw.kovidgoyal.net/kitty/keyboard-protocol/
+pub fn parse_kitty_key(params: &str) -> Option<EditorKey> {
+    let mut params = params.split(';');
+    // sub-parameters such as alternate keys follow the value after a `:`
+    let code = params.next()?.split(':').next()?.parse::<u32>().ok()?;
+    let modifiers = params.next().and_then(|p| p.split(':').next());
+    let modifiers = Modifiers::from_param(modifiers.unwrap_or_default());
+
+    let c = match code {
+        127 => return Some(EditorKey::Backspace.with_modifiers(modifiers)),
+        9 if modifiers == Modifiers::SHIFT => return Some(EditorKey::ShiftTab),
+        // keys such as the ones on the keypad are in the private use area
+        57344..=63743 => return None,
+        _ => char::from_u32(code)?,
+    };
+    let key = match c {
+        ' ' | '@'..='_' | 'a'..='z' if modifiers.contains(Modifiers::CTRL) => EditorKey::ctrl(c),
+        c if modifiers.contains(Modifiers::SHIFT) => {
+            EditorKey::Char(c.to_uppercase().next().unwrap_or(c))
+        }
+        c => EditorKey::Char(c),
+    };
+    match key {
+        EditorKey::Char(c) if modifiers.contains(Modifiers::ALT) => Some(EditorKey::Alt(c)),
+        key => Some(key),
+    }
+}
+
+/// Turns on the kitty keyboard protocol if the terminal supports it.
+///
+/// Keys are then reported unambiguously, eg. Ctrl-H is no longer sent as Backspace
+/// and Esc is sent as <esc>[27u. Returns whether the protocol was turned on.
+pub fn enable_kitty_keyboard() -> io::Result<bool> {
+    // ?u - Query the keyboard flags, only answered by terminals which support the protocol
+    // c - Query the device attributes (answered by all terminals), its reply comes last
+    write(b"\x1b[?u\x1b[c")?;
+    io::stdout().flush()?;
+
+    let mut reply = Vec::new();
+    let mut supported = false;
+    // replies are of the form <esc>[?...u and <esc>[?...c
+    while wait_for_input(QUERY_TIMEOUT)? {
+        let b = read_byte()?;
+        if b == b'\x1b' {
+            reply.clear();
+        }
+        reply.push(b);
+        if reply.starts_with(b"\x1b[?") {
+            match b {
+                b'u' => supported = true,
+                b'c' => break,
+                _ => {}
+            }
+        }
+    }
+
+    if supported {
+        // >1u - Push the "disambiguate escape codes" flag, it's popped on exit
+        write(b"\x1b[>1u")?;
+        io::stdout().flush()?;
+        KITTY_KEYBOARD.store(true, Ordering::Relaxed);
+    }
+    Ok(supported)
+}
+
+/// Returns whether keys are reported using the kitty keyboard protocol
+pub fn kitty_keyboard() -> bool {
+    KITTY_KEYBOARD.load(Ordering::Relaxed)
+}
+
 extern "C" fn disable_raw_mode() {
+    if kitty_keyboard() {
+        // <u - Pop the keyboard flags pushed on startup
+        let _ = write(b"\x1b[<u");
+    }
+    // ?2004l - Disable bracketed paste mode, ?1006l/?1002l - Disable mouse reporting
+    let _ = write(b"\x1b[?2004l\x1b[?1006l\x1b[?1002l").and_then(|_| io::stdout().flush());
     unsafe {
-        if tcsetattr(STDIN_FILENO, TCSAFLUSH, &ORIG_TERMIOS) != 0 {
+        if tcsetattr(STDIN_FILENO, TCSAFLUSH, &raw const ORIG_TERMIOS) != 0 {
             die("Failed to disable raw mode", io::Error::last_os_error());
         };
     }
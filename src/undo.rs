@@ -0,0 +1,217 @@
+/// A single reversible modification to the rows of a buffer.
+///
+/// Positions are in chars (cx) and rows (cy), same as the cursor.
+#[derive(Clone)]
+pub enum Edit {
+    /// `chars` were inserted into row `y` starting at `x`
+    Insert {
+        x: usize,
+        y: usize,
+        chars: Vec<char>,
+    },
+    /// `chars` were removed from row `y` starting at `x`
+    Remove {
+        x: usize,
+        y: usize,
+        chars: Vec<char>,
+    },
+    /// Row `y` was broken into two at `x`
+    Split { x: usize, y: usize },
+    /// Row `y + 1` was appended to row `y` (which was `x` chars long)
+    Join { x: usize, y: usize },
+    /// A new row was inserted at index `at`
+    InsertRow { at: usize, chars: Vec<char> },
+    /// The row at index `at` was removed
+    RemoveRow { at: usize, chars: Vec<char> },
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Edit {
+        match self.clone() {
+            Edit::Insert { x, y, chars } => Edit::Remove { x, y, chars },
+            Edit::Remove { x, y, chars } => Edit::Insert { x, y, chars },
+            Edit::Split { x, y } => Edit::Join { x, y },
+            Edit::Join { x, y } => Edit::Split { x, y },
+            Edit::InsertRow { at, chars } => Edit::RemoveRow { at, chars },
+            Edit::RemoveRow { at, chars } => Edit::InsertRow { at, chars },
+        }
+    }
+}
+
+/// One undo step: a list of edits along with the cursor (cx, cy)
+/// before and after they were applied.
+#[derive(Clone)]
+pub struct Change {
+    id: usize,
+    pub edits: Vec<Edit>,
+    pub before: (usize, usize),
+    pub after: (usize, usize),
+}
+
+pub struct UndoStack {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+    next_id: usize,
+    /// Id of the change on top of the undo stack when the buffer was last saved
+    saved: Option<usize>,
+    /// When set, the next edit starts a new change instead of extending the last one
+    sealed: bool,
+    /// Nesting depth of `begin_group` calls
+    group_depth: usize,
+    /// Set once the first edit of the current group has been recorded
+    group_started: bool,
+}
+
+impl UndoStack {
+    pub fn new() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            next_id: 0,
+            saved: None,
+            sealed: true,
+            group_depth: 0,
+            group_started: false,
+        }
+    }
+
+    /// Records an edit that moved the cursor from `before` to `after`.
+    ///
+    /// Consecutive typing (or deleting) on the same row is merged into
+    /// the previous change so that it can be undone in one step.
+    pub fn record(&mut self, edit: Edit, before: (usize, usize), after: (usize, usize)) {
+        self.redo.clear();
+
+        if self.group_depth > 0 && self.group_started {
+            let last = self.undo.last_mut().unwrap();
+            if !Self::merge(last.edits.last_mut(), &edit) {
+                last.edits.push(edit);
+            }
+            last.after = after;
+            return;
+        }
+
+        if !self.sealed {
+            if let Some(last) = self.undo.last_mut() {
+                if last.after == before && Self::merge(last.edits.last_mut(), &edit) {
+                    last.after = after;
+                    return;
+                }
+            }
+        }
+
+        self.undo.push(Change {
+            id: self.next_id,
+            edits: vec![edit],
+            before,
+            after,
+        });
+        self.next_id += 1;
+        self.sealed = false;
+        self.group_started = self.group_depth > 0;
+    }
+
+    /// Tries to fold `edit` into `prev`, returns false if they can't be merged.
+    fn merge(prev: Option<&mut Edit>, edit: &Edit) -> bool {
+        match (prev, edit) {
+            (
+                Some(Edit::Insert { x, y, chars }),
+                Edit::Insert {
+                    x: nx,
+                    y: ny,
+                    chars: nchars,
+                },
+            ) if y == ny && *x + chars.len() == *nx => {
+                chars.extend(nchars);
+                true
+            }
+            (
+                Some(Edit::InsertRow { at, chars }),
+                Edit::Insert {
+                    x: nx,
+                    y: ny,
+                    chars: nchars,
+                },
+            ) if at == ny && chars.len() == *nx => {
+                chars.extend(nchars);
+                true
+            }
+            (
+                Some(Edit::Remove { x, y, chars }),
+                Edit::Remove {
+                    x: nx,
+                    y: ny,
+                    chars: nchars,
+                },
+            ) if y == ny && *nx + nchars.len() == *x => {
+                // backspacing: the new chars come before the ones already removed
+                chars.splice(0..0, nchars.iter().copied());
+                *x = *nx;
+                true
+            }
+            (
+                Some(Edit::Remove { x, y, chars }),
+                Edit::Remove {
+                    x: nx,
+                    y: ny,
+                    chars: nchars,
+                },
+            ) if y == ny && x == nx => {
+                // deleting forward: the new chars come after the ones already removed
+                chars.extend(nchars);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Starts recording a group of edits which are undone in a single step.
+    /// Groups can be nested, the outermost one is the one that counts.
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.sealed = true;
+            self.group_started = false;
+        }
+        self.group_depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+        if self.group_depth == 0 {
+            self.sealed = true;
+        }
+    }
+
+    /// Ends the current change, the next edit will start a new undo step.
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    /// Pops the last change, moving it to the redo stack.
+    /// The caller is responsible for applying the inverse of its edits.
+    pub fn undo(&mut self) -> Option<Change> {
+        self.sealed = true;
+        let change = self.undo.pop()?;
+        self.redo.push(change);
+        self.redo.last().cloned()
+    }
+
+    /// Pops the last undone change, moving it back to the undo stack.
+    /// The caller is responsible for re-applying its edits.
+    pub fn redo(&mut self) -> Option<Change> {
+        self.sealed = true;
+        let change = self.redo.pop()?;
+        self.undo.push(change);
+        self.undo.last().cloned()
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.sealed = true;
+        self.saved = self.undo.last().map(|c| c.id);
+    }
+
+    /// Returns true if the buffer is back at the state it was last saved in.
+    pub fn is_clean(&self) -> bool {
+        self.undo.last().map(|c| c.id) == self.saved
+    }
+}